@@ -0,0 +1,215 @@
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::mem;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+
+use super::{BlockArguments, ConcreteBlock, RcBlock};
+
+/// The error returned by a `Completion` whose block was released without
+/// being called.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Completion handler was released without being called")
+    }
+}
+
+impl Error for Cancelled { }
+
+enum State<T> {
+    Pending(Option<Waker>),
+    Completed(T),
+    Cancelled,
+    Finished,
+}
+
+type Shared<T> = Arc<Mutex<State<T>>>;
+
+fn lock<T>(shared: &Shared<T>) -> MutexGuard<'_, State<T>> {
+    shared.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Resolves the `Completion` when the block is first called, or cancels it
+/// when the block is released.
+struct Completer<A, T, F> {
+    shared: Shared<T>,
+    map: Mutex<Option<F>>,
+    _args: PhantomData<fn(A)>,
+}
+
+impl<A, T, F> Completer<A, T, F> where F: FnOnce(A) -> T {
+    fn complete(&self, args: A) {
+        let map = self.map.lock().unwrap_or_else(|e| e.into_inner()).take();
+        // Calls after the first are ignored
+        if let Some(map) = map {
+            let value = map(args);
+            finish(&self.shared, State::Completed(value));
+        }
+    }
+}
+
+impl<A, T, F> Drop for Completer<A, T, F> {
+    fn drop(&mut self) {
+        finish(&self.shared, State::Cancelled);
+    }
+}
+
+/// Moves a pending completion into `state` and wakes its task.
+fn finish<T>(shared: &Shared<T>, state: State<T>) {
+    let mut current = lock(shared);
+    if let State::Pending(ref mut waker) = *current {
+        let waker = waker.take();
+        *current = state;
+        drop(current);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/**
+A future that resolves when the block created with it by `completion_handler`
+is called.
+
+If the block is released without being called, the future resolves to
+`Err(Cancelled)`.
+*/
+pub struct Completion<T> {
+    shared: Shared<T>,
+}
+
+impl<T> Future for Completion<T> {
+    type Output = Result<T, Cancelled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut state = lock(&self.shared);
+        match *state {
+            State::Pending(ref mut waker) => {
+                *waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            State::Finished => panic!("Completion polled after it finished"),
+            _ => (),
+        }
+        match mem::replace(&mut *state, State::Finished) {
+            State::Completed(value) => Poll::Ready(Ok(value)),
+            _ => Poll::Ready(Err(Cancelled)),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Completion<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match *lock(&self.shared) {
+            State::Pending(_) => "Pending",
+            State::Completed(_) => "Completed",
+            State::Cancelled => "Cancelled",
+            State::Finished => "Finished",
+        };
+        f.debug_struct("Completion").field("state", &state).finish()
+    }
+}
+
+/**
+Creates a one-shot completion handler block and a `Completion` future which
+resolves when the block is called.
+
+When the block is first called, `map` is called with its arguments on the
+calling thread and the future resolves to the result; later calls are
+ignored. `map` is the place to retain any objects passed to the block, since
+they aren't guaranteed to outlive the call. If the block is released without
+being called, the future resolves to `Err(Cancelled)`.
+
+Objective-C may call or release the block on any thread, so `map` and its
+result must be `Send`. A closure capturing an `Rc` is rejected:
+
+``` compile_fail
+# use std::rc::Rc;
+# use objc::block::completion_handler;
+let rc = Rc::new(1);
+let _ = completion_handler(move |(): ()| *rc);
+```
+
+The returned `RcBlock` should be dropped once it has been passed to
+Objective-C, so that the block is released when Objective-C is done with it.
+The future doesn't depend on any particular executor.
+
+# Example
+``` no_run
+# #[macro_use] extern crate objc;
+# use objc::block::completion_handler;
+# use objc::runtime::{BOOL, Object};
+# fn main() {
+# let obj: *mut Object = 0 as *mut Object;
+let (block, completion) = completion_handler(|(success,): (BOOL,)| success);
+let _: () = unsafe {
+    msg_send![obj, saveWithCompletionHandler:&*block]
+};
+drop(block);
+// The result can now be awaited: `let success = completion.await;`
+# }
+```
+*/
+pub fn completion_handler<A, T, F>(map: F) -> (RcBlock<A, ()>, Completion<T>)
+        where A: BlockArguments + 'static, T: Send + 'static,
+        F: FnOnce(A) -> T + Send + 'static {
+    let shared = Arc::new(Mutex::new(State::Pending(None)));
+    let completer = Completer {
+        shared: shared.clone(),
+        map: Mutex::new(Some(map)),
+        _args: PhantomData,
+    };
+    let block = ConcreteBlock::with_tupled_closure(move |args: A| completer.complete(args));
+    (block.copy(), Completion { shared })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use super::{Cancelled, Completion, completion_handler};
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) { }
+    }
+
+    fn poll<T>(completion: &mut Completion<T>) -> Poll<Result<T, Cancelled>> {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+        Pin::new(completion).poll(&mut cx)
+    }
+
+    #[test]
+    fn test_completion_handler() {
+        let (block, mut completion) = completion_handler(|(a, b): (i32, i32)| a + b);
+        assert!(poll(&mut completion) == Poll::Pending);
+
+        unsafe {
+            block.call((2, 3));
+            // Only the first call completes the future
+            block.call((4, 5));
+        }
+        assert!(poll(&mut completion) == Poll::Ready(Ok(5)));
+    }
+
+    #[test]
+    fn test_completion_handler_cancelled() {
+        let (block, mut completion) = completion_handler(|(): ()| ());
+        let copied = block.clone();
+        drop(block);
+        assert!(poll(&mut completion) == Poll::Pending);
+
+        drop(copied);
+        assert!(poll(&mut completion) == Poll::Ready(Err(Cancelled)));
+    }
+}
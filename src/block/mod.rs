@@ -0,0 +1,796 @@
+/*!
+Support for Objective-C blocks.
+
+For more information on the specifics of the block implementation, see
+Clang's documentation: <http://clang.llvm.org/docs/Block-ABI-Apple.html>
+
+# Invoking blocks
+
+The `Block` struct is used for invoking blocks from Objective-C. For example,
+consider this Objective-C function:
+
+``` objc
+int32_t sum(int32_t (^block)(int32_t, int32_t)) {
+    return block(5, 8);
+}
+```
+
+We could write it in Rust as the following:
+
+```
+# use objc::block::Block;
+unsafe fn sum(block: &Block<(i32, i32), i32>) -> i32 {
+    block.call((5, 8))
+}
+```
+
+Note the extra parentheses in the `call` method, since the arguments must be
+passed as a tuple.
+
+Blocks compiled by clang include a type signature, which can be inspected with
+`Block::signature`. `Block::verify` checks that it matches the argument and
+return types of the `Block`, and `Block::call_verified` does so before calling
+the block:
+
+```
+# use objc::block::Block;
+# use objc::MessageError;
+unsafe fn sum(block: &Block<(i32, i32), i32>) -> Result<i32, MessageError> {
+    block.call_verified((5, 8))
+}
+```
+
+# Creating blocks
+
+Creating a block to pass to Objective-C can be done with the `ConcreteBlock`
+struct. For example, to create a block that adds two `i32`s, we could write:
+
+``` no_run
+# use objc::block::ConcreteBlock;
+let block = ConcreteBlock::new(|a: i32, b: i32| a + b);
+let block = block.copy();
+assert!(unsafe { block.call((5, 8)) } == 13);
+```
+
+It is important to copy your block to the heap (with the `copy` method) before
+passing it to Objective-C; this is because our `ConcreteBlock` is only meant
+to be copied once, and we can enforce this in Rust, but if Objective-C code
+were to copy it twice we could have a double free.
+
+Blocks that don't capture any state can instead be declared as statics with
+the `global_block!` macro; these never need to be copied.
+
+# Completion handlers
+
+APIs that report their result through a completion handler block can be
+awaited using `completion_handler`, which creates a one-shot block together
+with a `Completion` future that resolves when the block is called, or to
+`Err(Cancelled)` if the block is released without being called.
+*/
+
+use std::ffi::CStr;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::os::raw::{c_char, c_int, c_ulong, c_void};
+use std::ptr;
+
+use {encode, Encode, EncodeArguments, Encoding, Message, MessageError};
+
+mod completion;
+
+pub use self::completion::{Cancelled, Completion, completion_handler};
+
+/// The class of blocks, used as the isa of the block structures.
+#[repr(C)]
+struct BlockClass {
+    _priv: [u8; 0],
+}
+
+extern "C" {
+    static _NSConcreteStackBlock: BlockClass;
+    static _NSConcreteGlobalBlock: BlockClass;
+
+    fn _Block_copy(block: *const c_void) -> *mut c_void;
+    fn _Block_release(block: *const c_void);
+}
+
+const BLOCK_HAS_COPY_DISPOSE: c_int = 1 << 25;
+const BLOCK_IS_GLOBAL: c_int = 1 << 28;
+const BLOCK_USE_STRET: c_int = 1 << 29;
+const BLOCK_HAS_SIGNATURE: c_int = 1 << 30;
+
+/// Types that may be used as the arguments to an Objective-C block.
+pub trait BlockArguments: Sized {
+    /// Calls the given `Block` with self as the arguments.
+    ///
+    /// # Safety
+    ///
+    /// `block` must point to a valid `Block` and this invokes foreign code
+    /// whose safety the compiler cannot verify.
+    unsafe fn call_block<R>(self, block: *mut Block<Self, R>) -> R;
+
+    /// Returns the invoke function for a `ConcreteBlock` whose closure takes
+    /// its arguments as a single tuple.
+    #[doc(hidden)]
+    fn tupled_invoke<R, F>() -> unsafe extern "C" fn() where F: Fn(Self) -> R;
+}
+
+/// Types that may be converted into a `ConcreteBlock`.
+pub trait IntoConcreteBlock<A>: Sized where A: BlockArguments {
+    /// The return type of the resulting `ConcreteBlock`.
+    type Ret;
+
+    /// Consumes self to create a `ConcreteBlock`.
+    fn into_concrete_block(self) -> ConcreteBlock<A, Self::Ret, Self>;
+}
+
+macro_rules! block_args_impl {
+    ($($a:ident : $t:ident),*) => (
+        impl<$($t),*> BlockArguments for ($($t,)*) {
+            unsafe fn call_block<R>(self, block: *mut Block<Self, R>) -> R {
+                let invoke: unsafe extern "C" fn(*mut Block<Self, R> $(, $t)*) -> R =
+                    mem::transmute((*(block as *mut BlockBase)).invoke);
+                let ($($a,)*) = self;
+                invoke(block $(, $a)*)
+            }
+
+            fn tupled_invoke<R, X>() -> unsafe extern "C" fn() where X: Fn(Self) -> R {
+                unsafe extern "C" fn invoke<$($t,)* R, X>(
+                        block: *mut ConcreteBlock<($($t,)*), R, X> $(, $a: $t)*) -> R
+                        where X: Fn(($($t,)*)) -> R {
+                    ((*block).closure)(($($a,)*))
+                }
+
+                unsafe {
+                    mem::transmute::<
+                        unsafe extern "C" fn(*mut ConcreteBlock<($($t,)*), R, X> $(, $t)*) -> R,
+                        unsafe extern "C" fn(),
+                    >(invoke)
+                }
+            }
+        }
+
+        impl<$($t,)* R, X> IntoConcreteBlock<($($t,)*)> for X
+                where X: Fn($($t,)*) -> R {
+            type Ret = R;
+
+            fn into_concrete_block(self) -> ConcreteBlock<($($t,)*), R, X> {
+                unsafe extern "C" fn invoke<$($t,)* R, X>(
+                        block: *mut ConcreteBlock<($($t,)*), R, X> $(, $a: $t)*) -> R
+                        where X: Fn($($t,)*) -> R {
+                    ((*block).closure)($($a),*)
+                }
+
+                unsafe {
+                    let invoke = mem::transmute::<
+                        unsafe extern "C" fn(*mut ConcreteBlock<($($t,)*), R, X> $(, $t)*) -> R,
+                        unsafe extern "C" fn(),
+                    >(invoke);
+                    ConcreteBlock::with_invoke(invoke, self)
+                }
+            }
+        }
+    );
+}
+
+// Implements the traits for the given arguments and for each of their
+// suffixes, covering every arity up to the longest list.
+macro_rules! block_args_impls {
+    () => (
+        block_args_impl!();
+    );
+    ($a:ident : $t:ident $(, $b:ident : $u:ident)*) => (
+        block_args_impl!($a : $t $(, $b : $u)*);
+        block_args_impls!($($b : $u),*);
+    );
+}
+
+block_args_impls!(a1: A1, a2: A2, a3: A3, a4: A4, a5: A5, a6: A6, a7: A7, a8: A8,
+    a9: A9, a10: A10, a11: A11, a12: A12, a13: A13, a14: A14, a15: A15,
+    a16: A16, a17: A17, a18: A18, a19: A19, a20: A20, a21: A21, a22: A22,
+    a23: A23, a24: A24, a25: A25, a26: A26, a27: A27, a28: A28, a29: A29,
+    a30: A30, a31: A31, a32: A32);
+
+/// The layout shared by the start of all blocks.
+#[repr(C)]
+struct BlockBase {
+    isa: *const BlockClass,
+    flags: c_int,
+    _reserved: c_int,
+    invoke: unsafe extern "C" fn(),
+}
+
+/// The start of all blocks, followed by their captured variables.
+#[repr(C)]
+struct BlockHeader {
+    base: BlockBase,
+    descriptor: *const c_void,
+}
+
+/// An Objective-C block that takes arguments of `A` when called and
+/// returns a value of `R`.
+#[repr(C)]
+pub struct Block<A, R> {
+    _base: PhantomData<BlockBase>,
+    _args: PhantomData<fn(A) -> R>,
+}
+
+impl<A, R> Block<A, R> where A: BlockArguments {
+    /// Call self with the given arguments.
+    ///
+    /// # Safety
+    ///
+    /// This invokes foreign code that the caller must verify doesn't violate
+    /// any of Rust's safety rules. For example, if this block is shared with
+    /// multiple references, the caller must ensure that calling it will not
+    /// cause a data race.
+    pub unsafe fn call(&self, args: A) -> R {
+        args.call_block(self as *const _ as *mut _)
+    }
+}
+
+impl<A, R> Block<A, R> {
+    /// Returns the type signature of self, or `None` if self was created
+    /// without one.
+    pub fn signature(&self) -> Option<BlockSignature> {
+        let header = unsafe { &*(self as *const _ as *const BlockHeader) };
+        if header.base.flags & BLOCK_HAS_SIGNATURE == 0 {
+            return None;
+        }
+
+        // The descriptor holds its reserved field and size, then the copy and
+        // dispose helpers if the block has them, then the signature
+        let index = if header.base.flags & BLOCK_HAS_COPY_DISPOSE != 0 { 4 } else { 2 };
+        let signature = unsafe {
+            *(header.descriptor as *const *const c_char).add(index)
+        };
+        if signature.is_null() {
+            return None;
+        }
+        let signature = unsafe { CStr::from_ptr(signature) };
+        signature.to_str().ok().and_then(BlockSignature::parse)
+    }
+}
+
+impl<A, R> Block<A, R> where A: BlockArguments + EncodeArguments, R: Encode {
+    /**
+    Verifies that the argument and return types match the signature of self.
+
+    Returns a `MessageError` if self has no signature or if any encodings
+    differ for the arguments `A` and return type `R`.
+    */
+    pub fn verify(&self) -> Result<(), MessageError> {
+        let signature = match self.signature() {
+            Some(signature) => signature,
+            None => return Err(MessageError::Verification(
+                "Block has no signature".to_owned()
+            )),
+        };
+
+        let ret = R::encode();
+        let expected_ret = signature.return_type();
+        if ret != expected_ret {
+            return Err(MessageError::Verification(
+                format!("Return type code {:?} does not match expected {:?} for block",
+                    ret, expected_ret)
+            ));
+        }
+
+        let args = A::encodings();
+        let args = args.as_ref();
+        let block = [<*mut Block<A, R>>::encode()];
+
+        let count = block.len() + args.len();
+        let expected_count = signature.arguments_count();
+        if count != expected_count {
+            return Err(MessageError::Verification(
+                format!("Block accepts {} arguments, but {} were given",
+                    expected_count, count)
+            ));
+        }
+
+        for (i, arg) in block.iter().chain(args).enumerate() {
+            let expected = signature.argument_type(i).unwrap();
+            if *arg != expected {
+                return Err(MessageError::Verification(
+                    format!("Block expected argument at index {} with type code {:?} but was given {:?}",
+                        i, expected, arg)
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Verifies the signature of self and then calls it with the given
+    /// arguments, returning an error without calling self if verification
+    /// fails.
+    ///
+    /// # Safety
+    ///
+    /// The same requirements as for `call` apply.
+    pub unsafe fn call_verified(&self, args: A) -> Result<R, MessageError> {
+        self.verify().map(|_| self.call(args))
+    }
+}
+
+unsafe impl<A, R> Message for Block<A, R> { }
+
+/**
+The type signature of a block, describing the types of its arguments and
+return value.
+
+Like a method's receiver, the block itself is its first argument. Class names
+and other details of extended encodings are removed from the encodings.
+*/
+#[derive(Clone, Debug)]
+pub struct BlockSignature {
+    ret: Encoding,
+    args: Vec<Encoding>,
+}
+
+impl BlockSignature {
+    fn parse(code: &str) -> Option<BlockSignature> {
+        let (ret, mut rest) = encode::split_type(code)?;
+        let mut args = Vec::new();
+        while !rest.is_empty() {
+            let (arg, next) = encode::split_type(rest)?;
+            args.push(encode::from_str(&encode::strip_extended(arg)));
+            rest = next;
+        }
+        let ret = encode::from_str(&encode::strip_extended(ret));
+        Some(BlockSignature { ret, args })
+    }
+
+    /// Returns the `Encoding` of the return type.
+    pub fn return_type(&self) -> Encoding {
+        self.ret.clone()
+    }
+
+    /// Returns the `Encoding` of a single argument type, or `None` if there is
+    /// no argument at the given index.
+    pub fn argument_type(&self, index: usize) -> Option<Encoding> {
+        self.args.get(index).cloned()
+    }
+
+    /// Returns the number of arguments, including the block itself.
+    pub fn arguments_count(&self) -> usize {
+        self.args.len()
+    }
+}
+
+/// A reference-counted Objective-C block.
+pub struct RcBlock<A, R> {
+    ptr: *mut Block<A, R>,
+}
+
+impl<A, R> RcBlock<A, R> {
+    /// Construct an `RcBlock` for the given block without copying it.
+    /// The caller must ensure the block has a +1 reference count.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a valid `Block` and must have a +1 reference count
+    /// or it will be overreleased when the `RcBlock` is dropped.
+    pub unsafe fn new(ptr: *mut Block<A, R>) -> Self {
+        RcBlock { ptr }
+    }
+
+    /// Constructs an `RcBlock` by copying the given block.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a valid `Block`.
+    pub unsafe fn copy(ptr: *mut Block<A, R>) -> Self {
+        let ptr = _Block_copy(ptr as *const c_void) as *mut Block<A, R>;
+        RcBlock { ptr }
+    }
+}
+
+impl<A, R> Clone for RcBlock<A, R> {
+    fn clone(&self) -> RcBlock<A, R> {
+        unsafe {
+            RcBlock::copy(self.ptr)
+        }
+    }
+}
+
+impl<A, R> Deref for RcBlock<A, R> {
+    type Target = Block<A, R>;
+
+    fn deref(&self) -> &Block<A, R> {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<A, R> Drop for RcBlock<A, R> {
+    fn drop(&mut self) {
+        unsafe {
+            _Block_release(self.ptr as *const c_void);
+        }
+    }
+}
+
+/// An Objective-C block whose size is known at compile time and may be
+/// constructed on the stack.
+#[repr(C)]
+pub struct ConcreteBlock<A, R, F> {
+    base: BlockBase,
+    descriptor: Box<BlockDescriptor<ConcreteBlock<A, R, F>>>,
+    closure: F,
+    _args: PhantomData<fn(A) -> R>,
+}
+
+impl<A, R, F> ConcreteBlock<A, R, F>
+        where A: BlockArguments, F: IntoConcreteBlock<A, Ret=R> {
+    /// Constructs a `ConcreteBlock` with the given closure.
+    /// When the block is called, it will return the value that results from
+    /// calling the closure.
+    pub fn new(closure: F) -> Self {
+        closure.into_concrete_block()
+    }
+}
+
+impl<A, R, F> ConcreteBlock<A, R, F> where A: BlockArguments, F: Fn(A) -> R {
+    /// Constructs a `ConcreteBlock` with a closure that receives the arguments
+    /// of the block as a tuple.
+    fn with_tupled_closure(closure: F) -> Self {
+        unsafe {
+            ConcreteBlock::with_invoke(A::tupled_invoke::<R, F>(), closure)
+        }
+    }
+}
+
+impl<A, R, F> ConcreteBlock<A, R, F> {
+    /// Constructs a `ConcreteBlock` with the given invoke function and closure.
+    /// Unsafe because the caller must ensure the invoke function takes the
+    /// correct arguments.
+    pub(crate) unsafe fn with_invoke(invoke: unsafe extern "C" fn(), closure: F) -> Self {
+        ConcreteBlock {
+            base: BlockBase {
+                isa: &_NSConcreteStackBlock,
+                flags: BLOCK_HAS_COPY_DISPOSE,
+                _reserved: 0,
+                invoke,
+            },
+            descriptor: Box::new(BlockDescriptor::new()),
+            closure,
+            _args: PhantomData,
+        }
+    }
+
+    /// Marks self as returning its value in memory provided by the caller,
+    /// which `imp_implementationWithBlock` needs to know to pass the
+    /// arguments in the right places.
+    pub(crate) fn set_uses_stret(&mut self) {
+        self.base.flags |= BLOCK_USE_STRET;
+    }
+
+    /// Returns the closure called by self.
+    pub(crate) fn closure(&self) -> &F {
+        &self.closure
+    }
+}
+
+impl<A, R, F> ConcreteBlock<A, R, F> where F: 'static {
+    /// Copy self onto the heap as an `RcBlock`.
+    pub fn copy(self) -> RcBlock<A, R> {
+        unsafe {
+            let mut block = self;
+            let copied = RcBlock::copy(&mut *block);
+            // At this point, our copy helper has been run so the block will
+            // be moved to the heap and we can forget the original block
+            // because the heap block will drop in our dispose helper.
+            mem::forget(block);
+            copied
+        }
+    }
+}
+
+impl<A, R, F> Clone for ConcreteBlock<A, R, F> where F: Clone {
+    fn clone(&self) -> Self {
+        unsafe {
+            ConcreteBlock::with_invoke(self.base.invoke, self.closure.clone())
+        }
+    }
+}
+
+impl<A, R, F> Deref for ConcreteBlock<A, R, F> {
+    type Target = Block<A, R>;
+
+    fn deref(&self) -> &Block<A, R> {
+        unsafe { &*(&self.base as *const _ as *const Block<A, R>) }
+    }
+}
+
+impl<A, R, F> DerefMut for ConcreteBlock<A, R, F> {
+    fn deref_mut(&mut self) -> &mut Block<A, R> {
+        unsafe { &mut *(&mut self.base as *mut _ as *mut Block<A, R>) }
+    }
+}
+
+unsafe extern "C" fn block_context_dispose<B>(block: &mut B) {
+    // Read the block onto the stack and let it drop
+    ptr::read(block);
+}
+
+unsafe extern "C" fn block_context_copy<B>(_dst: &mut B, _src: &B) {
+    // The runtime memmoves the src block into the dst block, nothing to do
+}
+
+#[repr(C)]
+struct BlockDescriptor<B> {
+    _reserved: c_ulong,
+    block_size: c_ulong,
+    copy_helper: unsafe extern "C" fn(&mut B, &B),
+    dispose_helper: unsafe extern "C" fn(&mut B),
+}
+
+impl<B> BlockDescriptor<B> {
+    fn new() -> BlockDescriptor<B> {
+        BlockDescriptor {
+            _reserved: 0,
+            block_size: mem::size_of::<B>() as c_ulong,
+            copy_helper: block_context_copy::<B>,
+            dispose_helper: block_context_dispose::<B>,
+        }
+    }
+}
+
+/// The descriptor of global blocks, which have no copy or dispose helpers.
+#[repr(C)]
+struct GlobalBlockDescriptor {
+    _reserved: c_ulong,
+    block_size: c_ulong,
+}
+
+static GLOBAL_BLOCK_DESCRIPTOR: GlobalBlockDescriptor = GlobalBlockDescriptor {
+    _reserved: 0,
+    block_size: mem::size_of::<GlobalBlock<(), ()>>() as c_ulong,
+};
+
+/**
+An Objective-C block that captures no state and can be stored in a static.
+
+Global blocks are never moved by the runtime, so copying one just returns the
+same block and they may be passed to Objective-C without being copied first.
+They are declared with the `global_block!` macro.
+*/
+#[repr(C)]
+pub struct GlobalBlock<A, R> {
+    base: BlockBase,
+    descriptor: *const GlobalBlockDescriptor,
+    _args: PhantomData<fn(A) -> R>,
+}
+
+// Global blocks are immutable, the runtime never writes to them
+unsafe impl<A, R> Sync for GlobalBlock<A, R> { }
+
+impl<A, R> GlobalBlock<A, R> {
+    #[doc(hidden)]
+    pub const unsafe fn __new(invoke: unsafe extern "C" fn()) -> Self {
+        GlobalBlock {
+            base: BlockBase {
+                isa: &raw const _NSConcreteGlobalBlock,
+                flags: BLOCK_IS_GLOBAL,
+                _reserved: 0,
+                invoke,
+            },
+            descriptor: &GLOBAL_BLOCK_DESCRIPTOR,
+            _args: PhantomData,
+        }
+    }
+}
+
+impl<A, R> Deref for GlobalBlock<A, R> {
+    type Target = Block<A, R>;
+
+    fn deref(&self) -> &Block<A, R> {
+        unsafe { &*(&self.base as *const _ as *const Block<A, R>) }
+    }
+}
+
+/**
+Declares a static `GlobalBlock` with the given arguments and body.
+
+The body cannot capture any variables; if the return type is omitted it is
+`()`.
+
+# Example
+```
+# #[macro_use] extern crate objc;
+# fn main() {
+global_block! {
+    static ADD_ONE = |x: i32| -> i32 { x + 1 };
+}
+
+assert!(unsafe { ADD_ONE.call((2,)) } == 3);
+# }
+```
+*/
+#[macro_export]
+macro_rules! global_block {
+    ($vis:vis static $name:ident = |$($a:ident : $t:ty),*| $body:block;) => (
+        global_block! {
+            $vis static $name = |$($a : $t),*| -> () $body;
+        }
+    );
+    ($vis:vis static $name:ident = |$($a:ident : $t:ty),*| -> $r:ty $body:block;) => (
+        $vis static $name: $crate::block::GlobalBlock<($($t,)*), $r> = {
+            unsafe extern "C" fn invoke(
+                    _block: *mut $crate::block::Block<($($t,)*), $r>
+                    $(, $a: $t)*) -> $r $body
+
+            unsafe {
+                $crate::block::GlobalBlock::__new(::std::mem::transmute::<
+                    unsafe extern "C" fn(*mut $crate::block::Block<($($t,)*), $r> $(, $t)*) -> $r,
+                    unsafe extern "C" fn(),
+                >(invoke))
+            }
+        };
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+    use std::os::raw::{c_char, c_ulong};
+    use std::ptr;
+    use test_utils;
+    use Encode;
+    use super::{Block, BlockBase, ConcreteBlock, RcBlock};
+    use super::{_NSConcreteGlobalBlock, BLOCK_HAS_SIGNATURE, BLOCK_IS_GLOBAL};
+
+    #[test]
+    fn test_call_block() {
+        let block = ConcreteBlock::new(|| 13);
+        unsafe {
+            assert!(block.call(()) == 13);
+        }
+    }
+
+    #[test]
+    fn test_call_block_args() {
+        let block = ConcreteBlock::new(|a: i32, b: i32| a + b);
+        unsafe {
+            assert!(block.call((2, 13)) == 15);
+        }
+    }
+
+    #[test]
+    fn test_concrete_block_copy() {
+        let s = "Hello!".to_string();
+        let expected_len = s.len() as i32;
+        let block = ConcreteBlock::new(move || s.len() as i32);
+        assert!(unsafe { block.call(()) } == expected_len);
+
+        let copied = block.copy();
+        assert!(unsafe { copied.call(()) } == expected_len);
+        let cloned = copied.clone();
+        drop(copied);
+        assert!(unsafe { cloned.call(()) } == expected_len);
+    }
+
+    #[test]
+    fn test_concrete_block_stack_copy() {
+        fn make_block() -> RcBlock<(), i32> {
+            let x = 7;
+            let block = ConcreteBlock::new(move || x);
+            block.copy()
+        }
+
+        let block = make_block();
+        assert!(unsafe { block.call(()) } == 7);
+    }
+
+    #[test]
+    fn test_global_block() {
+        global_block! {
+            static TIMES_TWO = |x: i32| -> i32 { x * 2 };
+        }
+
+        assert!(unsafe { TIMES_TWO.call((4,)) } == 8);
+
+        // Copying a global block returns the same block
+        let block: &Block<(i32,), i32> = &TIMES_TWO;
+        let copied = unsafe { RcBlock::copy(block as *const _ as *mut _) };
+        assert!(ptr::eq(&*copied, block));
+        assert!(unsafe { copied.call((5,)) } == 10);
+    }
+
+    #[test]
+    fn test_block_message_argument() {
+        let obj = test_utils::custom_object();
+        let block = ConcreteBlock::new(|| 13).copy();
+        let result: i32 = unsafe {
+            msg_send![obj, invokeIntBlock:&*block]
+        };
+        assert!(result == 13);
+    }
+
+    #[repr(C)]
+    struct SignedBlockDescriptor {
+        _reserved: c_ulong,
+        block_size: c_ulong,
+        signature: *const c_char,
+    }
+
+    #[repr(C)]
+    struct SignedBlock {
+        base: BlockBase,
+        descriptor: *const SignedBlockDescriptor,
+    }
+
+    unsafe extern "C" fn signed_block_add(_block: *mut Block<(i32, i32), i32>,
+            a: i32, b: i32) -> i32 {
+        a + b
+    }
+
+    // Constructs a global block with a signature, like clang would emit
+    fn with_signed_block<F: FnOnce(&Block<(i32, i32), i32>)>(f: F) {
+        let descriptor = SignedBlockDescriptor {
+            _reserved: 0,
+            block_size: mem::size_of::<SignedBlock>() as c_ulong,
+            signature: "i16@?0i8i12\0".as_ptr() as *const c_char,
+        };
+        let block = SignedBlock {
+            base: BlockBase {
+                isa: unsafe { &_NSConcreteGlobalBlock },
+                flags: BLOCK_IS_GLOBAL | BLOCK_HAS_SIGNATURE,
+                _reserved: 0,
+                invoke: unsafe {
+                    mem::transmute::<
+                        unsafe extern "C" fn(*mut Block<(i32, i32), i32>, i32, i32) -> i32,
+                        unsafe extern "C" fn(),
+                    >(signed_block_add)
+                },
+            },
+            descriptor: &descriptor,
+        };
+        f(unsafe { &*(&block as *const _ as *const Block<(i32, i32), i32>) })
+    }
+
+    #[test]
+    fn test_block_signature() {
+        with_signed_block(|block| {
+            let signature = block.signature().unwrap();
+            assert!(signature.return_type().as_str() == "i");
+            assert!(signature.arguments_count() == 3);
+            assert!(signature.argument_type(0).unwrap().as_str() == "@?");
+            assert!(signature.argument_type(2).unwrap().as_str() == "i");
+            assert!(signature.argument_type(3).is_none());
+        });
+
+        let block = ConcreteBlock::new(|| 13);
+        assert!(block.signature().is_none());
+    }
+
+    #[test]
+    fn test_verify_block() {
+        with_signed_block(|block| {
+            assert!(block.verify().is_ok());
+            assert!(unsafe { block.call_verified((2, 3)) }.unwrap() == 5);
+
+            // Incorrect types
+            let block = unsafe {
+                &*(block as *const _ as *const Block<(i32, i32), u64>)
+            };
+            assert!(block.verify().is_err());
+            let block = unsafe {
+                &*(block as *const _ as *const Block<(i32,), i32>)
+            };
+            assert!(block.verify().is_err());
+        });
+
+        // Blocks without signatures can't be verified
+        let block = ConcreteBlock::new(|| 13);
+        assert!(block.verify().is_err());
+    }
+
+    #[test]
+    fn test_block_encode() {
+        assert!(<&Block<(), i32>>::encode().as_str() == "@?");
+        assert!(<*mut Block<(i32,), ()>>::encode().as_str() == "@?");
+    }
+}
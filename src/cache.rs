@@ -0,0 +1,59 @@
+use std::os::raw::c_char;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use runtime::{self, Class};
+
+/// Allows storing a `Class` reference in a static and lazily loading it.
+#[doc(hidden)]
+pub struct CachedClass {
+    ptr: AtomicPtr<Class>,
+}
+
+impl CachedClass {
+    /// Constructs a new `CachedClass`.
+    pub const fn new() -> CachedClass {
+        CachedClass { ptr: AtomicPtr::new(ptr::null_mut()) }
+    }
+
+    /// Returns the cached class. If no class is yet cached, gets one with
+    /// the given name and stores it.
+    ///
+    /// # Safety
+    ///
+    /// `name` must be a NUL-terminated string.
+    #[inline(always)]
+    pub unsafe fn get(&self, name: &str) -> Option<&'static Class> {
+        // `Relaxed` should be fine since `objc_getClass` is thread-safe.
+        let ptr = self.ptr.load(Ordering::Relaxed);
+        if ptr.is_null() {
+            let cls = runtime::objc_getClass(name.as_ptr() as *const c_char);
+            self.ptr.store(cls as *mut _, Ordering::Relaxed);
+            cls.as_ref()
+        } else {
+            Some(&*ptr)
+        }
+    }
+}
+
+impl Default for CachedClass {
+    fn default() -> CachedClass {
+        CachedClass::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_utils;
+
+    #[test]
+    fn test_class_macro() {
+        let cls = test_utils::custom_class();
+        assert!(class!(CustomObject) == cls);
+        // The second lookup at the same call site is served from the cache
+        for _ in 0..2 {
+            assert!(class!(CustomObject) == cls);
+        }
+        assert!(try_class!(NonExistentClass).is_none());
+    }
+}
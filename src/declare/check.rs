@@ -0,0 +1,126 @@
+use encode;
+use runtime::{Class, Method, Protocol, Sel};
+use Encoding;
+use super::DeclareError;
+
+/// Returns the method with the given selector implemented by `cls` or
+/// inherited from its superclasses. Unlike `Class::instance_method`, this
+/// can be used on classes that haven't been registered yet.
+fn find_method(cls: &Class, sel: Sel) -> Option<&Method> {
+    let mut current = Some(cls);
+    while let Some(c) = current {
+        if let Some(&method) = c.instance_methods().iter().find(|m| m.name() == sel) {
+            return Some(method);
+        }
+        current = c.superclass();
+    }
+    None
+}
+
+/// Returns the encodings of the return type and arguments of a method.
+fn signature(method: &Method) -> Vec<Encoding> {
+    let args = (0..method.arguments_count()).filter_map(|i| method.argument_type(i));
+    Some(method.return_type()).into_iter().chain(args).collect()
+}
+
+fn signature_str(signature: &[Encoding]) -> String {
+    signature.iter().map(|e| e.as_str()).collect()
+}
+
+/// Returns the encodings of a signature without the qualifiers and extended
+/// information that don't affect the types, for comparing signatures.
+fn comparable(signature: &[Encoding]) -> Vec<String> {
+    signature.iter().map(|e| {
+        encode::strip_qualifiers(&encode::strip_extended(e.as_str()))
+    }).collect()
+}
+
+/// Checks that the methods implemented by `cls` itself have the same
+/// signatures as the methods they override, ignoring type qualifiers.
+fn check_overrides(cls: &Class) -> Result<(), DeclareError> {
+    let superclass = match cls.superclass() {
+        Some(superclass) => superclass,
+        None => return Ok(()),
+    };
+    for method in cls.instance_methods().iter() {
+        let inherited = match find_method(superclass, method.name()) {
+            Some(inherited) => inherited,
+            None => continue,
+        };
+        let actual = signature(method);
+        let expected = signature(inherited);
+        if comparable(&actual) != comparable(&expected) {
+            return Err(DeclareError::OverrideMismatch {
+                sel: method.name(),
+                inherited: signature_str(&expected),
+                actual: signature_str(&actual),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Checks that `cls` implements the required methods of `proto` and the
+/// protocols it adopts.
+fn check_protocol(cls: &Class, proto: &Protocol) -> Result<(), DeclareError> {
+    for &is_instance in &[true, false] {
+        let implementer = if is_instance { cls } else { cls.metaclass() };
+        for description in proto.method_descriptions(true, is_instance).iter() {
+            if find_method(implementer, description.name()).is_none() {
+                return Err(DeclareError::MissingProtocolMethod {
+                    protocol: proto.name().to_owned(),
+                    sel: description.name(),
+                    is_instance,
+                });
+            }
+        }
+    }
+    for adopted in proto.adopted_protocols().iter() {
+        check_protocol(cls, adopted)?;
+    }
+    Ok(())
+}
+
+/// Checks that `cls` implements the required methods of the protocols it
+/// adopts, and that its methods and class methods have the same signatures
+/// as those they override.
+pub fn check_class(cls: &Class) -> Result<(), DeclareError> {
+    for proto in cls.adopted_protocols().iter() {
+        check_protocol(cls, proto)?;
+    }
+    check_overrides(cls)?;
+    check_overrides(cls.metaclass())
+}
+
+fn same_ivars(cls: &Class, declared: &Class) -> bool {
+    let ivars = cls.instance_variables();
+    let declared_ivars = declared.instance_variables();
+    ivars.len() == declared_ivars.len() &&
+        ivars.iter().zip(declared_ivars.iter()).all(|(ivar, declared)| {
+            ivar.name() == declared.name() &&
+                comparable(&[ivar.type_encoding()]) ==
+                    comparable(&[declared.type_encoding()])
+        })
+}
+
+fn implements_methods(cls: &Class, declared: &Class) -> bool {
+    let methods = cls.instance_methods();
+    declared.instance_methods().iter().all(|declared| {
+        methods.iter().any(|method| {
+            method.name() == declared.name() &&
+                comparable(&signature(method)) == comparable(&signature(declared))
+        })
+    })
+}
+
+/// Checks that `cls` is compatible with the class `declared`, which hasn't
+/// been registered: it must have the same superclass and ivars, implement
+/// the methods and class methods of `declared` with the same signatures, and
+/// conform to its protocols.
+pub fn is_compatible(cls: &Class, declared: &Class) -> bool {
+    cls.superclass() == declared.superclass() &&
+        same_ivars(cls, declared) &&
+        implements_methods(cls, declared) &&
+        implements_methods(cls.metaclass(), declared.metaclass()) &&
+        declared.adopted_protocols().iter().all(|proto| cls.conforms_to(proto))
+}
@@ -0,0 +1,301 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::mem;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use block::{Block, ConcreteBlock};
+use message::{encoded_returns_in_memory, invalidate_verified_methods};
+use runtime::{Class, Imp, Object, Sel, self};
+use {Encode, EncodeArguments, Message};
+use super::{DeclareError, add_method_imp, check_args, method_type_encoding};
+use super::panic::{PanicPolicy, abort_on_panic, catch_panic};
+
+/**
+Types that can be used as the implementation of an Objective-C method by
+wrapping them in a block.
+
+This is implemented for closures taking a reference to the receiver followed
+by the method's arguments, without the selector. `M` is a marker type which
+distinguishes closures taking `&T` from those taking `&mut T`; it is always
+inferred.
+
+Objective-C may call methods from any thread, so the closures must be `Send`
+and `Sync`. A closure capturing an `Rc` is rejected:
+
+``` compile_fail
+# #[macro_use] extern crate objc;
+# use std::rc::Rc;
+# use objc::declare::replace_method_with_closure;
+# use objc::runtime::{Class, Object};
+# fn main() {
+let cls = Class::get("NSObject").unwrap();
+let rc = Rc::new(1);
+unsafe {
+    replace_method_with_closure(cls, sel!(rustValue), move |_this: &Object| *rc);
+}
+# }
+```
+*/
+pub trait ClosureImplementation<M>: 'static {
+    /// The callee type of the method.
+    type Callee: Message;
+    /// The return type of the method.
+    type Ret: Encode;
+    /// The argument types of the method.
+    type Args: EncodeArguments;
+
+    /// Consumes self to create an `Imp` that calls it, aborting the process
+    /// if it panics.
+    ///
+    /// The closure is only freed if the returned `Imp` is passed to
+    /// `imp_removeBlock`.
+    fn imp(self) -> Imp;
+
+    /// Consumes self to create an `Imp` that calls it, handling panics
+    /// according to `policy`.
+    #[doc(hidden)]
+    fn catching_imp(self, policy: PanicPolicy<Self::Ret>) -> Imp
+        where Self::Ret: Clone;
+}
+
+/// Closures taking the receiver of a method followed by its arguments, which
+/// can be called from a block that implements the method.
+trait BlockClosure<T, A, R>: 'static {
+    fn into_imp(self) -> Imp;
+}
+
+/// Returns an `Imp` calling the given block, whose closure receives the
+/// receiver of the method followed by its arguments.
+fn block_imp<A, R, F>(mut block: ConcreteBlock<A, R, F>) -> Imp
+        where R: Any + Encode, F: 'static {
+    if encoded_returns_in_memory::<R>() {
+        block.set_uses_stret();
+    }
+    let block = block.copy();
+    // The runtime retains its own copy of the block, which is released by
+    // imp_removeBlock
+    let ptr = &*block as *const Block<A, R> as *mut Object;
+    unsafe { runtime::imp_implementationWithBlock(ptr) }
+}
+
+macro_rules! closure_impl {
+    (@ [$($m:tt)*] $($a:ident : $t:ident),*) => (
+        impl<T, R, X $(, $t)*> ClosureImplementation<fn(&$($m)* T $(, $t)*) -> R> for X
+                where T: Message + 'static, R: Encode + 'static,
+                X: Fn(&$($m)* T $(, $t)*) -> R + Send + Sync + 'static
+                $(, $t: Encode + 'static)* {
+            type Callee = T;
+            type Ret = R;
+            type Args = ($($t,)*);
+
+            fn imp(self) -> Imp {
+                BlockClosure::<T, ($($t,)*), R>::into_imp(
+                    move |this: *mut T $(, $a: $t)*| abort_on_panic(|| unsafe {
+                        self(&$($m)* *this $(, $a)*)
+                    }))
+            }
+
+            fn catching_imp(self, policy: PanicPolicy<R>) -> Imp where R: Clone {
+                BlockClosure::<T, ($($t,)*), R>::into_imp(
+                    move |this: *mut T $(, $a: $t)*| catch_panic(&policy, || unsafe {
+                        self(&$($m)* *this $(, $a)*)
+                    }))
+            }
+        }
+    );
+    ($($a:ident : $t:ident),*) => (
+        impl<T, R, X $(, $t)*> BlockClosure<T, ($($t,)*), R> for X
+                where T: 'static, R: Encode + 'static,
+                X: Fn(*mut T $(, $t)*) -> R + 'static $(, $t: 'static)* {
+            fn into_imp(self) -> Imp {
+                // Exceptions raised for panics must be able to unwind out of
+                // the block
+                unsafe extern "C-unwind" fn invoke<T, R, X $(, $t)*>(
+                        block: *mut ConcreteBlock<(*mut T, $($t,)*), R, X>,
+                        this: *mut T $(, $a: $t)*) -> R
+                        where X: Fn(*mut T $(, $t)*) -> R {
+                    ((*block).closure())(this $(, $a)*)
+                }
+
+                unsafe {
+                    let invoke = mem::transmute::<
+                        unsafe extern "C-unwind" fn(*mut ConcreteBlock<(*mut T, $($t,)*), R, X>,
+                            *mut T $(, $t)*) -> R,
+                        unsafe extern "C" fn(),
+                    >(invoke);
+                    block_imp::<(*mut T, $($t,)*), R, X>(
+                        ConcreteBlock::with_invoke(invoke, self))
+                }
+            }
+        }
+
+        closure_impl!(@ [] $($a : $t),*);
+        closure_impl!(@ [mut] $($a : $t),*);
+    );
+}
+
+// Implements the traits for closures taking the given argument types and for
+// each of their suffixes, covering every arity up to the longest list.
+macro_rules! closure_impls {
+    () => (
+        closure_impl!();
+    );
+    ($a:ident : $t:ident $(, $b:ident : $u:ident)*) => (
+        closure_impl!($a : $t $(, $b : $u)*);
+        closure_impls!($($b : $u),*);
+    );
+}
+
+closure_impls!(a1: A1, a2: A2, a3: A3, a4: A4, a5: A5, a6: A6, a7: A7, a8: A8,
+    a9: A9, a10: A10, a11: A11, a12: A12, a13: A13, a14: A14, a15: A15,
+    a16: A16, a17: A17, a18: A18, a19: A19, a20: A20, a21: A21, a22: A22,
+    a23: A23, a24: A24, a25: A25, a26: A26, a27: A27, a28: A28, a29: A29,
+    a30: A30, a31: A31, a32: A32);
+
+/// The `Imp`s created from closures, mapped to the class they were added to.
+fn closure_imps() -> MutexGuard<'static, HashMap<usize, usize>> {
+    static IMPS: OnceLock<Mutex<HashMap<usize, usize>>> = OnceLock::new();
+    IMPS.get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+/// Frees the closure of an `Imp` if it was created from one for `cls`.
+unsafe fn remove_closure_imp(cls: *const Class, imp: Imp) {
+    let removed = {
+        let mut imps = closure_imps();
+        match imps.get(&(imp as usize)) {
+            Some(&owner) if owner == cls as usize => {
+                imps.remove(&(imp as usize));
+                true
+            }
+            _ => false,
+        }
+    };
+    if removed {
+        runtime::imp_removeBlock(imp);
+    }
+}
+
+/// Frees the closures of all methods of `cls` created from closures.
+/// The methods must not be called afterwards.
+pub unsafe fn remove_closure_methods(cls: *const Class) {
+    let removed: Vec<usize> = {
+        let mut imps = closure_imps();
+        let removed = imps.iter()
+            .filter(|&(_, &owner)| owner == cls as usize)
+            .map(|(&imp, _)| imp)
+            .collect::<Vec<_>>();
+        for imp in &removed {
+            imps.remove(imp);
+        }
+        removed
+    };
+    for imp in removed {
+        runtime::imp_removeBlock(mem::transmute::<usize, Imp>(imp));
+    }
+}
+
+fn closure_types<F, M>(sel: Sel) -> Result<CString, DeclareError>
+        where F: ClosureImplementation<M> {
+    let encs = F::Args::encodings();
+    let encs = encs.as_ref();
+    check_args(sel, encs)?;
+    Ok(method_type_encoding(&F::Ret::encode(), encs))
+}
+
+/// Adds a method with the given `Imp` created from a closure to `cls`.
+/// The closure is freed if it couldn't be added.
+unsafe fn add_closure_imp(cls: *mut Class, sel: Sel, types: CString, imp: Imp)
+        -> Result<(), DeclareError> {
+    match add_method_imp(cls, sel, imp, &types) {
+        Ok(()) => {
+            closure_imps().insert(imp as usize, cls as usize);
+            Ok(())
+        }
+        Err(err) => {
+            runtime::imp_removeBlock(imp);
+            Err(err)
+        }
+    }
+}
+
+/// Adds a method implemented by the closure to `cls`.
+/// The process aborts if the closure panics.
+pub unsafe fn add_closure_method<F, M>(cls: *mut Class, sel: Sel, closure: F)
+        -> Result<(), DeclareError> where F: ClosureImplementation<M> {
+    let types = closure_types::<F, M>(sel)?;
+    add_closure_imp(cls, sel, types, closure.imp())
+}
+
+/// Adds a method implemented by the closure to `cls`.
+/// Panics are handled according to `policy`.
+pub unsafe fn add_catching_closure_method<F, M>(cls: *mut Class, sel: Sel,
+        closure: F, policy: PanicPolicy<F::Ret>) -> Result<(), DeclareError>
+        where F: ClosureImplementation<M>, F::Ret: Clone {
+    let types = closure_types::<F, M>(sel)?;
+    add_closure_imp(cls, sel, types, closure.catching_imp(policy))
+}
+
+/// Adds a method implemented by the closure to `cls`, replacing the
+/// implementation `cls` already declares for it, if any.
+/// The replaced implementation isn't freed, since the closure may call it.
+pub unsafe fn override_closure_method<F, M>(cls: *mut Class, sel: Sel, closure: F)
+        -> Result<(), DeclareError> where F: ClosureImplementation<M> {
+    let types = closure_types::<F, M>(sel)?;
+    let imp = closure.imp();
+    closure_imps().insert(imp as usize, cls as usize);
+    runtime::class_replaceMethod(cls, sel, imp, types.as_ptr());
+    Ok(())
+}
+
+unsafe fn replace_closure_method<F, M>(cls: *mut Class, sel: Sel, closure: F)
+        where F: ClosureImplementation<M> {
+    let types = closure_types::<F, M>(sel).unwrap_or_else(|e| panic!("{}", e));
+    let imp = closure.imp();
+    closure_imps().insert(imp as usize, cls as usize);
+    let previous = runtime::class_replaceMethod(cls, sel, imp, types.as_ptr());
+    invalidate_verified_methods(&*cls);
+    if let Some(previous) = previous {
+        remove_closure_imp(cls, previous);
+    }
+}
+
+/**
+Replaces the implementation of a method of an existing class with the given
+closure, or adds the method if the class doesn't implement it itself.
+Panics if the selector and closure take different numbers of arguments.
+
+If the previous implementation was also added from a closure, that closure is
+freed.
+
+# Safety
+
+The caller must ensure that the types match those that are expected when the
+method is invoked from Objective-C, and that the previous implementation isn't
+running when it is freed.
+*/
+pub unsafe fn replace_method_with_closure<F, M>(cls: &Class, sel: Sel, closure: F)
+        where F: ClosureImplementation<M, Callee=Object> {
+    replace_closure_method(cls as *const Class as *mut Class, sel, closure)
+}
+
+/**
+Replaces the implementation of a class method of an existing class with the
+given closure, or adds the class method if the class doesn't implement it
+itself. Panics if the selector and closure take different numbers of
+arguments.
+
+If the previous implementation was also added from a closure, that closure is
+freed.
+
+# Safety
+
+The same requirements as for `replace_method_with_closure` apply.
+*/
+pub unsafe fn replace_class_method_with_closure<F, M>(cls: &Class, sel: Sel,
+        closure: F) where F: ClosureImplementation<M, Callee=Class> {
+    let metaclass = cls.metaclass() as *const Class as *mut Class;
+    replace_closure_method(metaclass, sel, closure)
+}
@@ -0,0 +1,164 @@
+use message::invalidate_verified_methods;
+use runtime::{Class, Object, Protocol, Sel};
+use super::{ClosureImplementation, DeclareError, MethodImplementation,
+    add_method_impl, add_protocol};
+use super::closure::add_closure_method;
+
+/**
+A type for adding methods and protocols to an existing class, which may
+already be registered, like an Objective-C category.
+
+Unlike a category, a method that the class itself already implements is not
+replaced; adding it returns `DeclareError::DuplicateMethod` instead. Methods
+inherited from a superclass can be overridden.
+
+``` no_run
+# #[macro_use] extern crate objc;
+# use objc::declare::ClassExtension;
+# use objc::runtime::{Class, Object, Sel};
+# fn main() {
+extern "C" fn is_rusty(_this: &Object, _cmd: Sel) -> bool {
+    true
+}
+
+let cls = Class::get("NSObject").unwrap();
+let mut extension = ClassExtension::new(cls);
+unsafe {
+    extension.add_method(sel!(isRusty), is_rusty as extern "C" fn(&Object, Sel) -> bool);
+}
+# }
+```
+*/
+pub struct ClassExtension {
+    cls: *mut Class,
+}
+
+impl ClassExtension {
+    /// Constructs a `ClassExtension` for adding methods and protocols to
+    /// the given class.
+    pub fn new(cls: &Class) -> ClassExtension {
+        ClassExtension { cls: cls as *const Class as *mut Class }
+    }
+
+    /// Returns the class extended by self.
+    pub fn class(&self) -> &Class {
+        unsafe { &*self.cls }
+    }
+
+    /// Adds a method with the given name and implementation to the class.
+    /// Panics if the method wasn't sucessfully added, including if the class
+    /// already implements it, or if the selector and function take different
+    /// numbers of arguments.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the types match those that are expected
+    /// when the method is invoked from Objective-C.
+    pub unsafe fn add_method<F>(&mut self, sel: Sel, func: F)
+            where F: MethodImplementation<Callee=Object> {
+        self.try_add_method(sel, func).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Adds a method with the given name and implementation to the class.
+    /// Returns an error if the method wasn't sucessfully added, including if
+    /// the class already implements it, or if the selector and function take
+    /// different numbers of arguments.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the types match those that are expected
+    /// when the method is invoked from Objective-C.
+    pub unsafe fn try_add_method<F>(&mut self, sel: Sel, func: F)
+            -> Result<(), DeclareError>
+            where F: MethodImplementation<Callee=Object> {
+        add_method_impl(self.cls, sel, func)?;
+        // The method may override an inherited one that was already verified
+        invalidate_verified_methods(&*self.cls);
+        Ok(())
+    }
+
+    /// Adds a class method with the given name and implementation to the
+    /// class.
+    /// Panics if the method wasn't sucessfully added, including if the class
+    /// already implements it, or if the selector and function take different
+    /// numbers of arguments.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the types match those that are expected
+    /// when the method is invoked from Objective-C.
+    pub unsafe fn add_class_method<F>(&mut self, sel: Sel, func: F)
+            where F: MethodImplementation<Callee=Class> {
+        self.try_add_class_method(sel, func).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Adds a class method with the given name and implementation to the
+    /// class.
+    /// Returns an error if the method wasn't sucessfully added, including if
+    /// the class already implements it, or if the selector and function take
+    /// different numbers of arguments.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the types match those that are expected
+    /// when the method is invoked from Objective-C.
+    pub unsafe fn try_add_class_method<F>(&mut self, sel: Sel, func: F)
+            -> Result<(), DeclareError>
+            where F: MethodImplementation<Callee=Class> {
+        let metaclass = (*self.cls).metaclass() as *const _ as *mut _;
+        add_method_impl(metaclass, sel, func)?;
+        invalidate_verified_methods(&*metaclass);
+        Ok(())
+    }
+
+    /// Adds a method with the given name implemented by a closure to the
+    /// class. The closure receives the receiver followed by the method's
+    /// arguments, and is freed if the method is later replaced with
+    /// `replace_method_with_closure`.
+    /// Panics if the method wasn't sucessfully added, including if the class
+    /// already implements it, or if the selector and closure take different
+    /// numbers of arguments.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the types match those that are expected
+    /// when the method is invoked from Objective-C.
+    pub unsafe fn add_method_with_closure<F, M>(&mut self, sel: Sel, closure: F)
+            where F: ClosureImplementation<M, Callee=Object> {
+        add_closure_method(self.cls, sel, closure)
+            .unwrap_or_else(|e| panic!("{}", e));
+        invalidate_verified_methods(&*self.cls);
+    }
+
+    /// Adds a class method with the given name implemented by a closure to
+    /// the class. The closure receives the class followed by the method's
+    /// arguments, and is freed if the method is later replaced with
+    /// `replace_class_method_with_closure`.
+    /// Panics if the method wasn't sucessfully added, including if the class
+    /// already implements it, or if the selector and closure take different
+    /// numbers of arguments.
+    ///
+    /// # Safety
+    ///
+    /// The same requirements as for `add_method_with_closure` apply.
+    pub unsafe fn add_class_method_with_closure<F, M>(&mut self, sel: Sel,
+            closure: F) where F: ClosureImplementation<M, Callee=Class> {
+        let metaclass = (*self.cls).metaclass() as *const _ as *mut _;
+        add_closure_method(metaclass, sel, closure)
+            .unwrap_or_else(|e| panic!("{}", e));
+        invalidate_verified_methods(&*metaclass);
+    }
+
+    /// Adds a protocol to the class. Panics if the protocol wasn't
+    /// successfully added.
+    pub fn add_protocol(&mut self, proto: &Protocol) {
+        self.try_add_protocol(proto).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Adds a protocol to the class. Returns an error if the protocol wasn't
+    /// successfully added, such as if the class already conforms to it.
+    pub fn try_add_protocol(&mut self, proto: &Protocol)
+            -> Result<(), DeclareError> {
+        unsafe { add_protocol(self.cls, proto) }
+    }
+}
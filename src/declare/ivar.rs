@@ -0,0 +1,220 @@
+use std::cell::Cell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::OnceLock;
+
+use runtime::{Class, IvarError, Object};
+use {Encode, Encoding};
+use super::is_registered;
+
+/**
+A handle to an ivar of type `T` of a class, which accesses the ivar of its
+instances without looking it up by name.
+
+An `IvarRef` is returned by `ClassDecl::add_ivar` or can be created for an
+existing class with `IvarRef::new`. The ivar's offset is looked up the first
+time it's accessed after the class has been registered, and cached from then
+on.
+
+Accessing the ivar of an object that isn't an instance of the class panics.
+An `IvarRef` must not be used after its class has been disposed, such as by
+dropping an unregistered `ClassDecl` or a `ScopedClass`.
+*/
+pub struct IvarRef<T> {
+    cls: *const Class,
+    class_name: String,
+    name: String,
+    offset: OnceLock<isize>,
+    item: PhantomData<fn() -> T>,
+}
+
+// The class is never mutated through the pointer
+unsafe impl<T> Send for IvarRef<T> { }
+unsafe impl<T> Sync for IvarRef<T> { }
+
+impl<T> IvarRef<T> where T: Encode {
+    /// Creates an `IvarRef` for the ivar of `cls` with the given name.
+    /// Returns an error if `cls` has no ivar with the given name or its
+    /// encoding differs from that of `T`.
+    pub fn new(cls: &Class, name: &str) -> Result<IvarRef<T>, IvarError> {
+        let ivar = cls.instance_variable(name)
+            .ok_or_else(|| IvarError::NotFound(name.to_owned()))?;
+        let encoding = ivar.type_encoding();
+        if encoding == T::encode() {
+            Ok(IvarRef::declared(cls, name))
+        } else {
+            Err(IvarError::TypeMismatch {
+                name: name.to_owned(),
+                expected: T::encode(),
+                actual: encoding,
+            })
+        }
+    }
+
+    /// Creates an `IvarRef` for an ivar that was just added to `cls`.
+    pub(crate) fn declared(cls: &Class, name: &str) -> IvarRef<T> {
+        IvarRef {
+            cls,
+            class_name: cls.name().to_owned(),
+            name: name.to_owned(),
+            offset: OnceLock::new(),
+            item: PhantomData,
+        }
+    }
+
+    /// Returns the name of the ivar.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the offset of the ivar, caching it once the class has been
+    /// registered.
+    /// `obj` must be an instance of the class, which keeps it alive.
+    fn offset(&self) -> isize {
+        if let Some(&offset) = self.offset.get() {
+            return offset;
+        }
+        let cls = unsafe { &*self.cls };
+        let offset = cls.instance_variable(&self.name)
+            .filter(|ivar| ivar.type_encoding() == T::encode())
+            .unwrap_or_else(|| panic!("Ivar {} not found on class {}",
+                self.name, self.class_name))
+            .offset();
+        // Offsets may still change until the class is registered
+        if is_registered(cls) {
+            let _ = self.offset.set(offset);
+        }
+        offset
+    }
+
+    fn ptr(&self, obj: &Object) -> *mut T {
+        assert!(is_instance(obj, self.cls), "{:?} is not an instance of {}",
+            obj, self.class_name);
+        let obj_ptr = obj as *const Object as *mut u8;
+        unsafe { obj_ptr.offset(self.offset()) as *mut T }
+    }
+
+    /// Returns a reference to the ivar of `obj`.
+    /// Panics if `obj` isn't an instance of the class of the ivar or one of
+    /// its subclasses.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the ivar holds a valid `T`, which may not
+    /// be the case before it's initialized if `T` can't be zeroed.
+    pub unsafe fn get<'a>(&self, obj: &'a Object) -> &'a T {
+        &*self.ptr(obj)
+    }
+
+    /// Returns a mutable reference to the ivar of `obj`.
+    /// Panics if `obj` isn't an instance of the class of the ivar or one of
+    /// its subclasses.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the ivar holds a valid `T`, which may not
+    /// be the case before it's initialized if `T` can't be zeroed.
+    pub unsafe fn get_mut<'a>(&self, obj: &'a mut Object) -> &'a mut T {
+        &mut *self.ptr(obj)
+    }
+
+    /// Sets the value of the ivar of `obj`.
+    /// Panics if `obj` isn't an instance of the class of the ivar or one of
+    /// its subclasses.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the ivar holds a valid `T`, which may not
+    /// be the case before it's initialized if `T` can't be zeroed.
+    pub unsafe fn set(&self, obj: &mut Object, value: T) {
+        *self.get_mut(obj) = value;
+    }
+}
+
+/// Returns whether `obj` is an instance of `cls` or one of its subclasses.
+fn is_instance(obj: &Object, cls: *const Class) -> bool {
+    let mut current = Some(obj.class());
+    while let Some(c) = current {
+        if ptr::eq(c, cls) {
+            return true;
+        }
+        current = c.superclass();
+    }
+    false
+}
+
+impl<T> Clone for IvarRef<T> {
+    fn clone(&self) -> IvarRef<T> {
+        IvarRef {
+            cls: self.cls,
+            class_name: self.class_name.clone(),
+            name: self.name.clone(),
+            offset: self.offset.clone(),
+            item: PhantomData,
+        }
+    }
+}
+
+impl<T> fmt::Debug for IvarRef<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "IvarRef({}.{})", self.class_name, self.name)
+    }
+}
+
+/**
+A mutable ivar holding a `Copy` value, which can be read and written through
+a shared reference to its object.
+
+Methods usually receive `&Object`, which mustn't be converted to `&mut Object`
+to set ivars while other references to the object exist. An ivar of type
+`IvarCell<T>` can instead be updated through `Object::get_ivar` or
+`IvarRef::get`. Like a `Cell`, it must not be accessed from several threads at
+once; ivars shared between threads can use the atomic types from
+`std::sync::atomic` instead.
+
+The ivar has the same encoding as `T`, so it's also visible to Objective-C as
+an ivar of type `T`.
+*/
+#[repr(transparent)]
+#[derive(Default)]
+pub struct IvarCell<T> {
+    value: Cell<T>,
+}
+
+impl<T> IvarCell<T> where T: Copy {
+    /// Creates an `IvarCell` containing the given value.
+    pub fn new(value: T) -> IvarCell<T> {
+        IvarCell { value: Cell::new(value) }
+    }
+
+    /// Returns the value of self.
+    pub fn get(&self) -> T {
+        self.value.get()
+    }
+
+    /// Sets the value of self.
+    pub fn set(&self, value: T) {
+        self.value.set(value)
+    }
+
+    /// Sets the value of self, returning the previous value.
+    pub fn replace(&self, value: T) -> T {
+        self.value.replace(value)
+    }
+
+    /// Returns a raw pointer to the value of self.
+    pub fn as_ptr(&self) -> *mut T {
+        self.value.as_ptr()
+    }
+}
+
+unsafe impl<T> Encode for IvarCell<T> where T: Encode + Copy {
+    fn encode() -> Encoding { T::encode() }
+}
+
+impl<T> fmt::Debug for IvarCell<T> where T: Copy + fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("IvarCell").field(&self.get()).finish()
+    }
+}
@@ -0,0 +1,1496 @@
+/*!
+Functionality for declaring Objective-C classes.
+
+Classes can be declared using the `ClassDecl` struct. Instance variables and
+methods can then be added before the class is ultimately registered.
+
+# Example
+
+The following example demonstrates declaring a class named `MyNumber` that has
+one ivar, a `u32` named `_number` and a `number` method that returns it:
+
+``` no_run
+# #[macro_use] extern crate objc;
+# use objc::declare::ClassDecl;
+# use objc::runtime::{Class, Object, Sel};
+# fn main() {
+let superclass = Class::get("NSObject").unwrap();
+let mut decl = ClassDecl::new("MyNumber", superclass).unwrap();
+
+// Add an instance variable
+decl.add_ivar::<u32>("_number");
+
+// Add an ObjC method for getting the number
+extern fn my_number_get(this: &Object, _cmd: Sel) -> u32 {
+    unsafe { *this.get_ivar("_number") }
+}
+unsafe {
+    decl.add_method(sel!(number),
+        my_number_get as extern fn(&Object, Sel) -> u32);
+}
+
+decl.register();
+# }
+```
+
+`add_ivar` returns an `IvarRef`, which accesses the ivar of an instance
+without looking it up by name each time, unlike `Object::get_ivar`.
+
+Methods receive their object by shared reference, so ivars they update should
+be `IvarCell`s or atomics, which can be changed through it.
+
+Ivars added with `add_ivar` must implement `Encode` and are never dropped.
+Ivars holding any Rust value can instead be added with
+`ClassDecl::add_rust_ivar`; the value is initialized when an instance is
+allocated with `+allocWithZone:` (which `+alloc` and `+new` use) and dropped
+when the instance receives `-dealloc`, by overrides of these methods that are
+added when the class is registered.
+
+Methods and protocols can be added to existing classes, including registered
+ones, with a `ClassExtension`, much like an Objective-C category.
+
+Classes registered with `ClassDecl::register_scoped` are disposed when the
+returned `ScopedClass` is dropped, once they have no instances left, so that
+tests can declare many throwaway classes.
+
+Properties can be added with `ClassDecl::add_property`, or synthesized along
+with their ivar and accessor methods with `ClassDecl::synthesize_property` if
+they're nonatomic.
+
+Classes can also be declared with the `declare_class!` macro, which builds on
+`ClassDecl` to declare a class along with a Rust type for its instances.
+
+Methods can also be implemented by Rust closures, which may capture state,
+with `ClassDecl::add_method_with_closure`. Methods of existing classes can be
+replaced with closures using `replace_method_with_closure`. The closures are
+wrapped in blocks and turned into method implementations with the runtime's
+`imp_implementationWithBlock`, which both the Apple and GNUstep runtimes
+provide. A closure is freed when its method is replaced by another closure or
+when an unregistered `ClassDecl` is dropped.
+
+Panics must not unwind from a method into Objective-C. The process aborts if
+a closure added with `add_method_with_closure` panics, while closures added
+with `ClassDecl::add_method_catching` handle panics according to a
+`PanicPolicy`: aborting, returning a default value, or raising an
+`NSException`. Functions added with `ClassDecl::add_method` can wrap their
+body in `catch_panic` for the same effect.
+*/
+
+use std::error::Error;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use message::invalidate_verified_methods;
+use runtime::{BOOL, Class, Imp, NO, Object, Protocol, Sel, self};
+use {Encode, EncodeArguments, Encoding, Message};
+
+mod check;
+mod closure;
+mod extension;
+mod ivar;
+mod panic;
+mod property;
+mod rust_ivar;
+mod scoped;
+
+pub use self::closure::{ClosureImplementation, replace_class_method_with_closure,
+    replace_method_with_closure};
+pub use self::extension::ClassExtension;
+pub use self::ivar::{IvarCell, IvarRef};
+pub use self::panic::{PanicPolicy, catch_panic};
+pub use self::property::{PropertyAttributes, PropertySemantics};
+pub use self::scoped::ScopedClass;
+use self::rust_ivar::RustIvar;
+
+/// Types that can be used as the implementation of an Objective-C method.
+pub trait MethodImplementation {
+    /// The callee type of the method.
+    type Callee: Message;
+    /// The return type of the method.
+    type Ret: Encode;
+    /// The argument types of the method.
+    type Args: EncodeArguments;
+
+    /// Returns self as an `Imp` of a method.
+    fn imp(self) -> Imp;
+}
+
+macro_rules! method_decl_impl {
+    (-$s:ident, $r:ident, $f:ty, $($t:ident),*) => (
+        impl<$s, $r $(, $t)*> MethodImplementation for $f
+                where $s: Message, $r: Encode $(, $t: Encode)* {
+            type Callee = $s;
+            type Ret = $r;
+            type Args = ($($t,)*);
+
+            fn imp(self) -> Imp {
+                unsafe { mem::transmute(self) }
+            }
+        }
+    );
+    ($($t:ident),*) => (
+        method_decl_impl!(-T, R, extern fn(&T, Sel $(, $t)*) -> R, $($t),*);
+        method_decl_impl!(-T, R, extern fn(&mut T, Sel $(, $t)*) -> R, $($t),*);
+        method_decl_impl!(-T, R, extern "C-unwind" fn(&T, Sel $(, $t)*) -> R, $($t),*);
+        method_decl_impl!(-T, R, extern "C-unwind" fn(&mut T, Sel $(, $t)*) -> R, $($t),*);
+    );
+}
+
+// Implements the trait for functions taking the given argument types and for
+// each of their suffixes, covering every arity up to the longest list.
+macro_rules! method_decl_impls {
+    () => (
+        method_decl_impl!();
+    );
+    ($t:ident $(, $u:ident)*) => (
+        method_decl_impl!($t $(, $u)*);
+        method_decl_impls!($($u),*);
+    );
+}
+
+method_decl_impls!(A1, A2, A3, A4, A5, A6, A7, A8, A9, A10, A11, A12, A13, A14,
+    A15, A16, A17, A18, A19, A20, A21, A22, A23, A24, A25, A26, A27, A28, A29,
+    A30, A31, A32);
+
+/// An error that occurred while declaring a class or protocol.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeclareError {
+    /// A class with the given name already exists.
+    DuplicateClass(String),
+    /// A class with the given name already exists, but has a different
+    /// superclass.
+    IncompatibleClass(String),
+    /// A protocol with the given name already exists.
+    DuplicateProtocol(String),
+    /// The class already implements a method with the given selector.
+    DuplicateMethod(Sel),
+    /// The class already has an ivar with the given name.
+    DuplicateIvar(String),
+    /// The class already declares a property with the given name.
+    DuplicateProperty(String),
+    /// The selector and the implementation take different numbers of
+    /// arguments.
+    ArgumentCountMismatch {
+        /// The selector of the method.
+        sel: Sel,
+        /// The number of arguments the selector accepts.
+        expected: usize,
+        /// The number of arguments the implementation accepts.
+        actual: usize,
+    },
+    /// The class doesn't implement a required method of a protocol it
+    /// adopts.
+    MissingProtocolMethod {
+        /// The name of the protocol.
+        protocol: String,
+        /// The selector of the method.
+        sel: Sel,
+        /// Whether the method is an instance method rather than a class
+        /// method.
+        is_instance: bool,
+    },
+    /// A method of the class overrides an inherited method with a different
+    /// signature.
+    OverrideMismatch {
+        /// The selector of the method.
+        sel: Sel,
+        /// The encoded return and argument types of the inherited method.
+        inherited: String,
+        /// The encoded return and argument types of the overriding method.
+        actual: String,
+    },
+    /// The given name contains an interior nul byte.
+    InvalidName(String),
+    /// The property with the given name can't be synthesized, because it's
+    /// atomic or its type doesn't suit its memory semantics.
+    InvalidProperty(String),
+    /// The runtime refused the described operation.
+    RuntimeRefused(String),
+}
+
+impl fmt::Display for DeclareError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DeclareError::DuplicateClass(ref name) =>
+                write!(f, "A class named {} already exists", name),
+            DeclareError::IncompatibleClass(ref name) =>
+                write!(f, "A class named {} already exists with a different superclass", name),
+            DeclareError::DuplicateProtocol(ref name) =>
+                write!(f, "A protocol named {} already exists", name),
+            DeclareError::DuplicateMethod(sel) =>
+                write!(f, "Method {:?} is already implemented by the class", sel),
+            DeclareError::DuplicateIvar(ref name) =>
+                write!(f, "Ivar {} already exists", name),
+            DeclareError::DuplicateProperty(ref name) =>
+                write!(f, "Property {} already exists", name),
+            DeclareError::ArgumentCountMismatch { sel, expected, actual } =>
+                write!(f, "Selector {:?} accepts {} arguments, but the implementation accepts {}",
+                    sel, expected, actual),
+            DeclareError::MissingProtocolMethod { ref protocol, sel, is_instance } =>
+                write!(f, "Required {} method {:?} of protocol {} is not implemented",
+                    if is_instance { "instance" } else { "class" }, sel, protocol),
+            DeclareError::OverrideMismatch { sel, ref inherited, ref actual } =>
+                write!(f, "Method {:?} has types {}, but overrides a method with types {}",
+                    sel, actual, inherited),
+            DeclareError::InvalidName(ref name) =>
+                write!(f, "Name {:?} contains a nul byte", name),
+            DeclareError::InvalidProperty(ref name) =>
+                write!(f, "Property {} can't be synthesized unless it's nonatomic and, if it retains, copies or weakly references its value, has an object type",
+                    name),
+            DeclareError::RuntimeRefused(ref operation) =>
+                write!(f, "The runtime refused to {}", operation),
+        }
+    }
+}
+
+impl Error for DeclareError {
+    fn description(&self) -> &str {
+        match *self {
+            DeclareError::DuplicateClass(_) => "duplicate class",
+            DeclareError::IncompatibleClass(_) => "incompatible class",
+            DeclareError::DuplicateProtocol(_) => "duplicate protocol",
+            DeclareError::DuplicateMethod(_) => "duplicate method",
+            DeclareError::DuplicateIvar(_) => "duplicate ivar",
+            DeclareError::DuplicateProperty(_) => "duplicate property",
+            DeclareError::ArgumentCountMismatch { .. } => "argument count mismatch",
+            DeclareError::MissingProtocolMethod { .. } => "missing protocol method",
+            DeclareError::OverrideMismatch { .. } => "override signature mismatch",
+            DeclareError::InvalidName(_) => "invalid name",
+            DeclareError::InvalidProperty(_) => "invalid property",
+            DeclareError::RuntimeRefused(_) => "refused by the runtime",
+        }
+    }
+}
+
+fn count_args(sel: Sel) -> usize {
+    sel.name().chars().filter(|&c| c == ':').count()
+}
+
+/// Checks that the selector accepts as many arguments as are encoded.
+fn check_args(sel: Sel, encs: &[Encoding]) -> Result<(), DeclareError> {
+    let sel_args = count_args(sel);
+    if sel_args == encs.len() {
+        Ok(())
+    } else {
+        Err(DeclareError::ArgumentCountMismatch {
+            sel,
+            expected: sel_args,
+            actual: encs.len(),
+        })
+    }
+}
+
+fn c_name(name: &str) -> Result<CString, DeclareError> {
+    CString::new(name).map_err(|_| DeclareError::InvalidName(name.to_owned()))
+}
+
+/// Adds a method to `cls`, explaining why if it couldn't be added.
+unsafe fn add_method_imp(cls: *mut Class, sel: Sel, imp: Imp, types: &CStr)
+        -> Result<(), DeclareError> {
+    let success = runtime::class_addMethod(cls, sel, imp, types.as_ptr());
+    if success != NO {
+        Ok(())
+    } else if (*cls).instance_methods().iter().any(|m| m.name() == sel) {
+        Err(DeclareError::DuplicateMethod(sel))
+    } else {
+        Err(DeclareError::RuntimeRefused(format!("add method {:?}", sel)))
+    }
+}
+
+/// Adds a method implemented by `func` to `cls`, checking its arguments.
+unsafe fn add_method_impl<F>(cls: *mut Class, sel: Sel, func: F)
+        -> Result<(), DeclareError> where F: MethodImplementation {
+    let encs = F::Args::encodings();
+    let encs = encs.as_ref();
+    check_args(sel, encs)?;
+
+    let types = method_type_encoding(&F::Ret::encode(), encs);
+    add_method_imp(cls, sel, func.imp(), &types)
+}
+
+/// Adds a protocol to `cls`.
+unsafe fn add_protocol(cls: *mut Class, proto: &Protocol)
+        -> Result<(), DeclareError> {
+    if runtime::class_addProtocol(cls, proto) != NO {
+        Ok(())
+    } else {
+        Err(DeclareError::RuntimeRefused(format!("add protocol {:?}", proto)))
+    }
+}
+
+fn method_type_encoding(ret: &Encoding, args: &[Encoding]) -> CString {
+    let mut types = ret.as_str().to_owned();
+    // First two arguments are always self and the selector
+    types.push_str(<*mut Object>::encode().as_str());
+    types.push_str(Sel::encode().as_str());
+    types.extend(args.iter().map(|e| e.as_str()));
+    CString::new(types).unwrap()
+}
+
+/// Returns whether `cls` has been registered with the runtime.
+fn is_registered(cls: &Class) -> bool {
+    Class::get(cls.name()).is_some_and(|c| ptr::eq(c, cls))
+}
+
+fn log2_align_of<T>() -> u8 {
+    let align = mem::align_of::<T>();
+    // Alignments are required to be powers of 2
+    debug_assert!(align.count_ones() == 1);
+    // log2 of a power of 2 is the number of trailing zeros
+    align.trailing_zeros() as u8
+}
+
+/// A type for declaring a new class and adding new methods and ivars to it
+/// before registering it.
+pub struct ClassDecl {
+    cls: *mut Class,
+    rust_ivars: Vec<RustIvar>,
+}
+
+impl ClassDecl {
+    fn with_superclass(name: &str, superclass: Option<&Class>)
+            -> Result<ClassDecl, DeclareError> {
+        let c_name = c_name(name)?;
+        let super_ptr = superclass.map_or(ptr::null(), |c| c);
+        let cls = unsafe {
+            runtime::objc_allocateClassPair(super_ptr, c_name.as_ptr(), 0)
+        };
+        if !cls.is_null() {
+            Ok(ClassDecl { cls, rust_ivars: Vec::new() })
+        } else if Class::get(name).is_some() {
+            Err(DeclareError::DuplicateClass(name.to_owned()))
+        } else {
+            Err(DeclareError::RuntimeRefused(format!("allocate class {}", name)))
+        }
+    }
+
+    /// Constructs a `ClassDecl` with the given name and superclass.
+    /// Returns `None` if the class couldn't be allocated.
+    pub fn new(name: &str, superclass: &Class) -> Option<ClassDecl> {
+        ClassDecl::try_new(name, superclass).ok()
+    }
+
+    /// Constructs a `ClassDecl` with the given name and superclass.
+    /// Returns an error describing why if the class couldn't be allocated.
+    pub fn try_new(name: &str, superclass: &Class)
+            -> Result<ClassDecl, DeclareError> {
+        ClassDecl::with_superclass(name, Some(superclass))
+    }
+
+    /// Constructs a `ClassDecl` with the given superclass and a unique name
+    /// made of the given prefix followed by a number, for classes that only
+    /// need to be distinct from one another.
+    /// Returns `None` if the class couldn't be allocated.
+    pub fn new_unique(prefix: &str, superclass: &Class) -> Option<ClassDecl> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        loop {
+            let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+            let name = format!("{}{}", prefix, count);
+            match ClassDecl::try_new(&name, superclass) {
+                // The name may have been taken outside this crate
+                Err(DeclareError::DuplicateClass(_)) => continue,
+                result => return result.ok(),
+            }
+        }
+    }
+
+    /**
+    Returns the class with the given name if it has already been registered,
+    and otherwise declares it by calling `declare` with a new `ClassDecl` and
+    registers it.
+
+    If the class exists, `declare` is called with a scratch `ClassDecl` under
+    a unique name, which is disposed afterwards, to check that the existing
+    class is compatible: it must have the given superclass and the same ivars,
+    and implement the declared methods with the same signatures. Otherwise
+    `DeclareError::IncompatibleClass` is returned. `declare` therefore must
+    not keep references to the class it declares, like an `IvarRef`; those
+    can be created for the returned class with `IvarRef::new` instead.
+
+    Calls to `get_or_register` within this copy of the crate are serialized,
+    so the class is only declared once by them; `declare` must not call
+    `get_or_register` itself. If other code registers the class at the same
+    time, it's checked for compatibility like an existing class. This can
+    replace guarding the declaration with a `Once`:
+
+    ``` no_run
+    # #[macro_use] extern crate objc;
+    # use objc::declare::ClassDecl;
+    # use objc::runtime::Class;
+    # fn main() {
+    let superclass = Class::get("NSObject").unwrap();
+    let cls = ClassDecl::get_or_register("MyNumber", superclass, |decl| {
+        decl.add_ivar::<u32>("_number");
+    }).unwrap();
+    # }
+    ```
+    */
+    pub fn get_or_register<F>(name: &str, superclass: &Class, declare: F)
+            -> Result<&'static Class, DeclareError>
+            where F: FnOnce(&mut ClassDecl) {
+        static REGISTRATION: Mutex<()> = Mutex::new(());
+
+        let _guard = REGISTRATION.lock().unwrap_or_else(|e| e.into_inner());
+        if Class::get(name).is_none() {
+            match ClassDecl::try_new(name, superclass) {
+                Ok(mut decl) => {
+                    declare(&mut decl);
+                    return Ok(decl.register());
+                }
+                // The class may have been registered outside this crate since
+                Err(DeclareError::DuplicateClass(_)) => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        let cls = Class::get(name)
+            .ok_or_else(|| DeclareError::DuplicateClass(name.to_owned()))?;
+        if !cls.superclass().is_some_and(|c| ptr::eq(c, superclass)) {
+            return Err(DeclareError::IncompatibleClass(name.to_owned()));
+        }
+        let mut scratch = ClassDecl::new_unique(name, superclass)
+            .ok_or_else(|| DeclareError::RuntimeRefused(format!("allocate class {}", name)))?;
+        declare(&mut scratch);
+        if check::is_compatible(cls, unsafe { &*scratch.cls }) {
+            Ok(cls)
+        } else {
+            Err(DeclareError::IncompatibleClass(name.to_owned()))
+        }
+    }
+
+    /**
+    Constructs a `ClassDecl` declaring a new root class with the given name.
+    Returns `None` if the class couldn't be allocated.
+
+    An implementation for `+initialize` must also be given; the runtime calls
+    this method for all classes, so it must be defined on root classes.
+
+    Note that implementing a root class is not a simple endeavor.
+    For example, your class probably cannot be passed to Cocoa code unless
+    the entire `NSObject` protocol is implemented.
+    Functionality it expects, like implementations of `-retain` and `-release`
+    used by ARC, will not be present otherwise.
+    */
+    pub fn root(name: &str, intitialize_fn: extern fn(&Class, Sel))
+            -> Option<ClassDecl> {
+        let mut decl = ClassDecl::with_superclass(name, None).ok();
+        if let Some(ref mut decl) = decl {
+            unsafe {
+                decl.add_class_method(sel!(initialize), intitialize_fn);
+            }
+        }
+        decl
+    }
+
+    /// Adds a method with the given name and implementation to self.
+    /// Panics if the method wasn't sucessfully added
+    /// or if the selector and function take different numbers of arguments.
+    /// Unsafe because the caller must ensure that the types match those that
+    /// are expected when the method is invoked from Objective-C.
+    pub unsafe fn add_method<F>(&mut self, sel: Sel, func: F)
+            where F: MethodImplementation<Callee=Object> {
+        self.try_add_method(sel, func).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Adds a method with the given name and implementation to self.
+    /// Returns an error if the method wasn't sucessfully added
+    /// or if the selector and function take different numbers of arguments.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the types match those that are expected
+    /// when the method is invoked from Objective-C.
+    pub unsafe fn try_add_method<F>(&mut self, sel: Sel, func: F)
+            -> Result<(), DeclareError>
+            where F: MethodImplementation<Callee=Object> {
+        add_method_impl(self.cls, sel, func)
+    }
+
+    /// Adds a class method with the given name and implementation to self.
+    /// Panics if the method wasn't sucessfully added
+    /// or if the selector and function take different numbers of arguments.
+    /// Unsafe because the caller must ensure that the types match those that
+    /// are expected when the method is invoked from Objective-C.
+    pub unsafe fn add_class_method<F>(&mut self, sel: Sel, func: F)
+            where F: MethodImplementation<Callee=Class> {
+        self.try_add_class_method(sel, func).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Adds a class method with the given name and implementation to self.
+    /// Returns an error if the method wasn't sucessfully added
+    /// or if the selector and function take different numbers of arguments.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the types match those that are expected
+    /// when the method is invoked from Objective-C.
+    pub unsafe fn try_add_class_method<F>(&mut self, sel: Sel, func: F)
+            -> Result<(), DeclareError>
+            where F: MethodImplementation<Callee=Class> {
+        let metaclass = (*self.cls).metaclass() as *const _ as *mut _;
+        add_method_impl(metaclass, sel, func)
+    }
+
+    /// Adds a method with the given name implemented by a closure to self.
+    /// The closure receives the receiver followed by the method's arguments
+    /// and is freed if self is dropped without being registered.
+    /// Panics if the method wasn't sucessfully added
+    /// or if the selector and closure take different numbers of arguments.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the types match those that are expected
+    /// when the method is invoked from Objective-C.
+    pub unsafe fn add_method_with_closure<F, M>(&mut self, sel: Sel, closure: F)
+            where F: ClosureImplementation<M, Callee=Object> {
+        closure::add_closure_method(self.cls, sel, closure)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// Adds a class method with the given name implemented by a closure to
+    /// self. The closure receives the class followed by the method's
+    /// arguments and is freed if self is dropped without being registered.
+    /// Panics if the method wasn't sucessfully added
+    /// or if the selector and closure take different numbers of arguments.
+    ///
+    /// # Safety
+    ///
+    /// The same requirements as for `add_method_with_closure` apply.
+    pub unsafe fn add_class_method_with_closure<F, M>(&mut self, sel: Sel,
+            closure: F) where F: ClosureImplementation<M, Callee=Class> {
+        let metaclass = (*self.cls).metaclass() as *const _ as *mut _;
+        closure::add_closure_method(metaclass, sel, closure)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// Adds a method with the given name implemented by a closure to self,
+    /// handling panics in the closure according to `policy`.
+    /// Panics if the method wasn't sucessfully added
+    /// or if the selector and closure take different numbers of arguments.
+    ///
+    /// # Safety
+    ///
+    /// The same requirements as for `add_method_with_closure` apply.
+    pub unsafe fn add_method_catching<F, M>(&mut self, sel: Sel,
+            policy: PanicPolicy<F::Ret>, closure: F)
+            where F: ClosureImplementation<M, Callee=Object>, F::Ret: Clone {
+        closure::add_catching_closure_method(self.cls, sel, closure, policy)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// Adds a class method with the given name implemented by a closure to
+    /// self, handling panics in the closure according to `policy`.
+    /// Panics if the method wasn't sucessfully added
+    /// or if the selector and closure take different numbers of arguments.
+    ///
+    /// # Safety
+    ///
+    /// The same requirements as for `add_method_with_closure` apply.
+    pub unsafe fn add_class_method_catching<F, M>(&mut self, sel: Sel,
+            policy: PanicPolicy<F::Ret>, closure: F)
+            where F: ClosureImplementation<M, Callee=Class>, F::Ret: Clone {
+        let metaclass = (*self.cls).metaclass() as *const _ as *mut _;
+        closure::add_catching_closure_method(metaclass, sel, closure, policy)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// Adds an ivar with type `T` and the provided name to self, returning
+    /// an `IvarRef` for accessing it.
+    /// Panics if the ivar wasn't successfully added.
+    pub fn add_ivar<T>(&mut self, name: &str) -> IvarRef<T> where T: Encode {
+        self.try_add_ivar::<T>(name).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Adds an ivar with type `T` and the provided name to self, returning
+    /// an `IvarRef` for accessing it.
+    /// Returns an error if the ivar wasn't successfully added.
+    pub fn try_add_ivar<T>(&mut self, name: &str)
+            -> Result<IvarRef<T>, DeclareError> where T: Encode {
+        let c_name = c_name(name)?;
+        let encoding = CString::new(T::encode().as_str()).unwrap();
+        let size = mem::size_of::<T>();
+        let align = log2_align_of::<T>();
+        let success = unsafe {
+            runtime::class_addIvar(self.cls, c_name.as_ptr(), size, align,
+                encoding.as_ptr())
+        };
+        let cls = unsafe { &*self.cls };
+        if success != NO {
+            Ok(IvarRef::declared(cls, name))
+        } else if cls.instance_variable(name).is_some() {
+            Err(DeclareError::DuplicateIvar(name.to_owned()))
+        } else {
+            Err(DeclareError::RuntimeRefused(format!("add ivar {}", name)))
+        }
+    }
+
+    /// Adds a protocol to self. Panics if the protocol wasn't successfully
+    /// added
+    pub fn add_protocol(&mut self, proto: &Protocol) {
+        self.try_add_protocol(proto).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Adds a protocol to self. Returns an error if the protocol wasn't
+    /// successfully added.
+    pub fn try_add_protocol(&mut self, proto: &Protocol)
+            -> Result<(), DeclareError> {
+        unsafe { add_protocol(self.cls, proto) }
+    }
+
+    /// Registers self, consuming it and returning a reference to the
+    /// newly registered `Class`.
+    pub fn register(mut self) -> &'static Class {
+        unsafe {
+            let cls = self.cls;
+            let rust_ivars = mem::take(&mut self.rust_ivars);
+            if !rust_ivars.is_empty() {
+                rust_ivar::add_rust_ivar_methods(cls, rust_ivars)
+                    .unwrap_or_else(|e| panic!("{}", e));
+            }
+            runtime::objc_registerClassPair(cls);
+            // Forget self otherwise the class will be disposed in drop
+            mem::forget(self);
+            &*cls
+        }
+    }
+
+    /// Registers self like `register` after checking that the class
+    /// implements the required instance and class methods of every protocol
+    /// it adopts, and that its methods have the same argument and return
+    /// types as the inherited methods they override.
+    /// Returns an error and disposes the class if a check fails.
+    pub fn register_checked(self) -> Result<&'static Class, DeclareError> {
+        check::check_class(unsafe { &*self.cls })?;
+        Ok(self.register())
+    }
+}
+
+/// Disposes a class and its metaclass, freeing the closures of their methods.
+/// The class must have no instances or subclasses.
+unsafe fn dispose_class(cls: *mut Class) {
+    invalidate_verified_methods(&*cls);
+    invalidate_verified_methods((*cls).metaclass());
+    closure::remove_closure_methods(cls);
+    closure::remove_closure_methods((*cls).metaclass());
+    runtime::objc_disposeClassPair(cls);
+}
+
+impl Drop for ClassDecl {
+    fn drop(&mut self) {
+        unsafe {
+            dispose_class(self.cls);
+        }
+    }
+}
+
+/// A type for declaring a new protocol and adding new methods to it
+/// before registering it.
+///
+/// If a `ProtocolDecl` is dropped without being registered, the protocol is
+/// freed; neither runtime provides a function for disposing it, so its name
+/// and the lists of its method descriptions are leaked. A registered protocol
+/// can't be unregistered.
+pub struct ProtocolDecl {
+    proto: *mut Protocol
+}
+
+impl ProtocolDecl {
+    /// Constructs a `ProtocolDecl` with the given name. Returns `None` if the
+    /// protocol couldn't be allocated.
+    pub fn new(name: &str) -> Option<ProtocolDecl> {
+        ProtocolDecl::try_new(name).ok()
+    }
+
+    /// Constructs a `ProtocolDecl` with the given name. Returns an error
+    /// describing why if the protocol couldn't be allocated.
+    pub fn try_new(name: &str) -> Result<ProtocolDecl, DeclareError> {
+        let c_name = c_name(name)?;
+        let proto = unsafe {
+            runtime::objc_allocateProtocol(c_name.as_ptr())
+        };
+        if !proto.is_null() {
+            Ok(ProtocolDecl { proto })
+        } else if Protocol::get(name).is_some() {
+            Err(DeclareError::DuplicateProtocol(name.to_owned()))
+        } else {
+            Err(DeclareError::RuntimeRefused(format!("allocate protocol {}", name)))
+        }
+    }
+
+    fn add_method_description_common<Args, Ret>(&mut self, sel: Sel, is_required: bool,
+            is_instance_method: bool) -> Result<(), DeclareError>
+            where Args: EncodeArguments,
+                  Ret: Encode {
+        let encs = Args::encodings();
+        let encs = encs.as_ref();
+        check_args(sel, encs)?;
+        let types = method_type_encoding(&Ret::encode(), encs);
+        unsafe {
+            runtime::protocol_addMethodDescription(
+                self.proto, sel, types.as_ptr(), is_required as BOOL, is_instance_method as BOOL);
+        }
+        Ok(())
+    }
+
+    /// Adds an instance method declaration with a given description to self.
+    pub fn add_method_description<Args, Ret>(&mut self, sel: Sel, is_required: bool)
+            where Args: EncodeArguments,
+                  Ret: Encode {
+        self.try_add_method_description::<Args, Ret>(sel, is_required)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Adds an instance method declaration with a given description to self.
+    /// Returns an error if the selector and arguments take different numbers
+    /// of arguments.
+    pub fn try_add_method_description<Args, Ret>(&mut self, sel: Sel,
+            is_required: bool) -> Result<(), DeclareError>
+            where Args: EncodeArguments,
+                  Ret: Encode {
+        self.add_method_description_common::<Args, Ret>(sel, is_required, true)
+    }
+
+    /// Adds a class method declaration with a given description to self.
+    pub fn add_class_method_description<Args, Ret>(&mut self, sel: Sel, is_required: bool)
+            where Args: EncodeArguments,
+                  Ret: Encode {
+        self.try_add_class_method_description::<Args, Ret>(sel, is_required)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Adds a class method declaration with a given description to self.
+    /// Returns an error if the selector and arguments take different numbers
+    /// of arguments.
+    pub fn try_add_class_method_description<Args, Ret>(&mut self, sel: Sel,
+            is_required: bool) -> Result<(), DeclareError>
+            where Args: EncodeArguments,
+                  Ret: Encode {
+        self.add_method_description_common::<Args, Ret>(sel, is_required, false)
+    }
+
+    /// Adds a requirement on another protocol.
+    pub fn add_protocol(&mut self, proto: &Protocol) {
+        unsafe {
+            runtime::protocol_addProtocol(self.proto, proto);
+        }
+    }
+
+    /// Registers self, consuming it and returning a reference to the
+    /// newly registered `Protocol`.
+    pub fn register(self) -> &'static Protocol {
+        unsafe {
+            let proto = self.proto;
+            runtime::objc_registerProtocol(proto);
+            // Forget self otherwise the protocol will be freed in drop
+            mem::forget(self);
+            &*proto
+        }
+    }
+}
+
+impl Drop for ProtocolDecl {
+    fn drop(&mut self) {
+        // Both runtimes allocate protocols with calloc and only keep track of
+        // them once they're registered
+        unsafe {
+            runtime::free(self.proto as *mut c_void);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::os::raw::c_char;
+    use std::ptr;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use runtime::{Class, Object, Protocol};
+    use test_utils;
+    use runtime::Sel;
+    use super::{ClassDecl, ClassExtension, DeclareError, IvarCell, IvarRef,
+        PanicPolicy, PropertyAttributes, PropertySemantics, ProtocolDecl,
+        replace_class_method_with_closure, replace_method_with_closure};
+
+    declare_class! {
+        struct MacroNumber: CustomObject <CustomProtocol> {
+            _number: u32,
+            _scale: u32,
+        }
+
+        impl MacroNumber {
+            #[sel(number)]
+            fn number(&self) -> u32 {
+                unsafe { *self.get_ivar::<u32>("_number") * *self.get_ivar::<u32>("_scale") }
+            }
+
+            #[sel(setNumber:scale:)]
+            fn set_number(&mut self, number: u32, scale: u32) {
+                unsafe {
+                    self.set_ivar("_number", number);
+                    self.set_ivar("_scale", scale);
+                }
+            }
+
+            #[sel(numberScale)]
+            fn number_scale() -> u32 {
+                10
+            }
+        }
+    }
+
+    #[test]
+    fn test_custom_class() {
+        // Registering the custom class is in test_utils
+        let obj = test_utils::custom_object();
+        unsafe {
+            let _: () = msg_send![obj, setFoo:13u32];
+            let result: u32 = msg_send![obj, foo];
+            assert!(result == 13);
+        }
+    }
+
+    #[test]
+    fn test_many_arguments() {
+        // The 16 argument method is declared in test_utils
+        let obj = test_utils::custom_object();
+        unsafe {
+            let result: u32 = msg_send![obj, sumA:1u32 b:2u32 c:3u32 d:4u32
+                e:5u32 f:6u32 g:7u32 h:8u32 i:9u32 j:10u32 k:11u32 l:12u32
+                m:13u32 n:14u32 o:15u32 p:16u32];
+            assert!(result == 136);
+        }
+    }
+
+    #[test]
+    fn test_class_method() {
+        let cls = test_utils::custom_class();
+        unsafe {
+            let result: u32 = msg_send![cls, classFoo];
+            assert!(result == 7);
+        }
+    }
+
+    #[test]
+    fn test_closure_method() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let superclass = test_utils::custom_class();
+        let mut decl = ClassDecl::new("ClosureMethodClass", superclass).unwrap();
+        let captured = count.clone();
+        unsafe {
+            decl.add_method_with_closure(sel!(incrementBy:),
+                move |_this: &Object, n: u32| {
+                    captured.fetch_add(n as usize, Ordering::SeqCst) as u32 + n
+                });
+            decl.add_class_method_with_closure(sel!(classBar),
+                |_cls: &Class| 11u32);
+        }
+        let cls = decl.register();
+
+        unsafe {
+            let obj: *mut Object = msg_send![cls, new];
+            let result: u32 = msg_send![obj, incrementBy:3u32];
+            assert!(result == 3);
+            let result: u32 = msg_send![obj, incrementBy:4u32];
+            assert!(result == 7);
+            let _: () = msg_send![obj, release];
+
+            let result: u32 = msg_send![cls, classBar];
+            assert!(result == 11);
+        }
+        assert!(count.load(Ordering::SeqCst) == 7);
+    }
+
+    #[test]
+    fn test_closure_freed_with_decl() {
+        let state = Arc::new(());
+        let superclass = test_utils::custom_class();
+        let mut decl = ClassDecl::new("ClosureDisposedClass", superclass).unwrap();
+        let captured = state.clone();
+        unsafe {
+            decl.add_method_with_closure(sel!(state),
+                move |_this: &Object| Arc::strong_count(&captured));
+        }
+        assert!(Arc::strong_count(&state) == 2);
+        drop(decl);
+        assert!(Arc::strong_count(&state) == 1);
+    }
+
+    #[test]
+    fn test_replace_method_with_closure() {
+        let superclass = test_utils::custom_class();
+        let cls = ClassDecl::new("ClosureReplacedClass", superclass).unwrap()
+            .register();
+        let first = Arc::new(());
+        let captured = first.clone();
+        unsafe {
+            replace_method_with_closure(cls, sel!(foo),
+                move |_this: &Object| Arc::strong_count(&captured) as u32);
+            replace_class_method_with_closure(cls, sel!(classFoo),
+                |_cls: &Class| 8u32);
+
+            let obj: *mut Object = msg_send![cls, new];
+            let result: u32 = msg_send![obj, foo];
+            assert!(result == 2);
+
+            // Replacing the closure frees its state
+            replace_method_with_closure(cls, sel!(foo), |_this: &Object| 5u32);
+            assert!(Arc::strong_count(&first) == 1);
+            let result: u32 = msg_send![obj, foo];
+            assert!(result == 5);
+            let _: () = msg_send![obj, release];
+
+            let result: u32 = msg_send![cls, classFoo];
+            assert!(result == 8);
+        }
+    }
+
+    #[test]
+    fn test_method_catching_panic() {
+        let superclass = test_utils::custom_class();
+        let mut decl = ClassDecl::new("PanickingClass", superclass).unwrap();
+        unsafe {
+            decl.add_method_catching(sel!(checkedDivide:),
+                PanicPolicy::Return(0u32),
+                |_this: &Object, n: u32| 12 / n);
+        }
+        let cls = decl.register();
+
+        unsafe {
+            let obj: *mut Object = msg_send![cls, new];
+            let result: u32 = msg_send![obj, checkedDivide:4u32];
+            assert!(result == 3);
+            let result: u32 = msg_send![obj, checkedDivide:0u32];
+            assert!(result == 0);
+            let _: () = msg_send![obj, release];
+        }
+    }
+
+    #[test]
+    fn test_declare_errors() {
+        let superclass = test_utils::custom_class();
+        let err = ClassDecl::try_new("CustomObject", superclass).err();
+        assert!(err == Some(DeclareError::DuplicateClass("CustomObject".to_owned())));
+        let err = ClassDecl::try_new("Invalid\0Name", superclass).err();
+        assert!(err == Some(DeclareError::InvalidName("Invalid\0Name".to_owned())));
+
+        extern "C" fn get_number(_this: &Object, _cmd: Sel) -> u32 { 0 }
+        let get_number: extern "C" fn(&Object, Sel) -> u32 = get_number;
+
+        let mut decl = ClassDecl::try_new("DeclareErrorClass", superclass).unwrap();
+        unsafe {
+            assert!(decl.try_add_method(sel!(number), get_number).is_ok());
+            let err = decl.try_add_method(sel!(number), get_number).err();
+            assert!(err == Some(DeclareError::DuplicateMethod(sel!(number))));
+            let err = decl.try_add_method(sel!(numberAt:), get_number).err();
+            assert!(err == Some(DeclareError::ArgumentCountMismatch {
+                sel: sel!(numberAt:),
+                expected: 1,
+                actual: 0,
+            }));
+        }
+        assert!(decl.try_add_ivar::<u32>("_number").is_ok());
+        let err = decl.try_add_ivar::<u32>("_number").err();
+        assert!(err == Some(DeclareError::DuplicateIvar("_number".to_owned())));
+
+        let err = ProtocolDecl::try_new("CustomProtocol").err();
+        assert!(err == Some(DeclareError::DuplicateProtocol("CustomProtocol".to_owned())));
+    }
+
+    #[test]
+    fn test_declare_class_macro() {
+        // The superclass and protocol are registered in test_utils
+        let superclass = test_utils::custom_class();
+        let cls = MacroNumber::class();
+        assert!(cls.name() == "MacroNumber");
+        assert!(cls.superclass().is_some_and(|c| ptr::eq(c, superclass)));
+        assert!(cls.conforms_to(test_utils::custom_protocol()));
+        assert!(cls.instance_variable("_number").is_some());
+        // The class is only registered once
+        assert!(ptr::eq(MacroNumber::class(), cls));
+
+        unsafe {
+            let obj: &mut MacroNumber = msg_send![cls, new];
+            let _: () = msg_send![obj, setNumber:4u32 scale:3u32];
+            let result: u32 = msg_send![obj, number];
+            assert!(result == 12);
+            assert!(obj.number() == 12);
+            let _: () = msg_send![obj, release];
+
+            let scale: u32 = msg_send![cls, numberScale];
+            assert!(scale == MacroNumber::number_scale());
+        }
+    }
+
+    #[test]
+    fn test_rust_ivar() {
+        let instances = Arc::new(());
+        let superclass = test_utils::custom_class();
+        let mut decl = ClassDecl::new("RustIvarClass", superclass).unwrap();
+        decl.add_rust_ivar::<Vec<u32>>("_numbers");
+        let captured = instances.clone();
+        decl.add_rust_ivar_with("_instances", move || captured.clone());
+        let cls = decl.register();
+
+        unsafe {
+            let obj: *mut Object = msg_send![cls, new];
+            assert!(Arc::strong_count(&instances) == 3);
+            (*obj).get_mut_rust_ivar::<Vec<u32>>("_numbers").push(4);
+            assert!(*(*obj).get_rust_ivar::<Vec<u32>>("_numbers") == [4]);
+            // Other ivars are unaffected
+            let _: () = msg_send![obj, setFoo:13u32];
+            let foo: u32 = msg_send![obj, foo];
+            assert!(foo == 13);
+
+            let _: () = msg_send![obj, release];
+        }
+        assert!(Arc::strong_count(&instances) == 2);
+    }
+
+    #[test]
+    fn test_rust_ivar_dropped_on_release() {
+        struct Counted(Arc<AtomicUsize>);
+
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let superclass = match Class::get("NSObject") {
+            Some(superclass) => superclass,
+            // Test with the Objective-C memory management of NSObject
+            None => return,
+        };
+        let drops = Arc::new(AtomicUsize::new(0));
+        let mut decl = ClassDecl::new("DroppedIvarClass", superclass).unwrap();
+        let captured = drops.clone();
+        decl.add_rust_ivar_with("_counted", move || Counted(captured.clone()));
+        let cls = decl.register();
+
+        // Subclasses inherit the overrides along with adding their own
+        let mut decl = ClassDecl::new("DroppedIvarSubclass", cls).unwrap();
+        let captured = drops.clone();
+        decl.add_rust_ivar_with("_subCounted", move || Counted(captured.clone()));
+        let subclass = decl.register();
+
+        unsafe {
+            let obj: *mut Object = msg_send![cls, new];
+            let _: () = msg_send![obj, release];
+            assert!(drops.load(Ordering::SeqCst) == 1);
+
+            let obj: *mut Object = msg_send![subclass, alloc];
+            let obj: *mut Object = msg_send![obj, init];
+            let _: *mut Object = msg_send![obj, retain];
+            let _: () = msg_send![obj, release];
+            assert!(drops.load(Ordering::SeqCst) == 1);
+            let _: () = msg_send![obj, release];
+            assert!(drops.load(Ordering::SeqCst) == 3);
+        }
+    }
+
+    #[test]
+    fn test_ivar_ref() {
+        let superclass = test_utils::custom_class();
+        let mut decl = ClassDecl::new("IvarRefClass", superclass).unwrap();
+        let number = decl.add_ivar::<u32>("_number");
+        let method_number = number.clone();
+        unsafe {
+            decl.add_method_with_closure(sel!(number), move |this: &Object| {
+                *method_number.get(this)
+            });
+        }
+        let cls = decl.register();
+
+        let foo = IvarRef::<u32>::new(cls, "_foo").unwrap();
+        assert!(IvarRef::<u8>::new(cls, "_foo").is_err());
+        assert!(IvarRef::<u32>::new(cls, "_bar").is_err());
+
+        unsafe {
+            let obj: *mut Object = msg_send![cls, new];
+            number.set(&mut *obj, 7);
+            assert!(*number.get(&*obj) == 7);
+            let result: u32 = msg_send![obj, number];
+            assert!(result == 7);
+
+            *foo.get_mut(&mut *obj) = 13;
+            let result: u32 = msg_send![obj, foo];
+            assert!(result == 13);
+            let _: () = msg_send![obj, release];
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "is not an instance of IvarRefOtherClass")]
+    fn test_ivar_ref_wrong_class() {
+        let superclass = test_utils::custom_class();
+        let mut decl = ClassDecl::new("IvarRefOtherClass", superclass).unwrap();
+        let number = decl.add_ivar::<u32>("_number");
+        decl.register();
+        assert!(format!("{:?}", number) == "IvarRef(IvarRefOtherClass._number)");
+
+        let obj = test_utils::custom_object();
+        unsafe {
+            number.get(&obj);
+        }
+    }
+
+    #[test]
+    fn test_ivar_cell() {
+        let superclass = test_utils::custom_class();
+        let mut decl = ClassDecl::new("IvarCellClass", superclass).unwrap();
+        let count = decl.add_ivar::<IvarCell<u32>>("_count");
+        decl.add_ivar::<AtomicUsize>("_total");
+        unsafe {
+            decl.add_method_with_closure(sel!(increment), move |this: &Object| {
+                let count = count.get(this);
+                count.set(count.get() + 1);
+                this.get_ivar::<AtomicUsize>("_total").fetch_add(2, Ordering::Relaxed);
+            });
+        }
+        let cls = decl.register();
+
+        unsafe {
+            let obj: *mut Object = msg_send![cls, new];
+            let _: () = msg_send![obj, increment];
+            let _: () = msg_send![obj, increment];
+            assert!((*obj).get_ivar::<IvarCell<u32>>("_count").get() == 2);
+            // The ivar is also visible as a plain u32
+            assert!(*(*obj).get_ivar::<u32>("_count") == 2);
+            let total = (*obj).get_ivar::<AtomicUsize>("_total");
+            assert!(total.load(Ordering::Relaxed) == 4);
+            let _: () = msg_send![obj, release];
+        }
+    }
+
+    #[test]
+    fn test_class_extension() {
+        extern "C" fn doubled_foo(this: &Object, _cmd: Sel) -> u32 {
+            unsafe { *this.get_ivar::<u32>("_foo") * 2 }
+        }
+
+        let superclass = test_utils::custom_class();
+        let decl = ClassDecl::new("ExtendedClass", superclass).unwrap();
+        let cls = decl.register();
+        let proto = {
+            let decl = ProtocolDecl::new("ExtensionProtocol").unwrap();
+            decl.register()
+        };
+
+        let mut extension = ClassExtension::new(cls);
+        let doubled_foo: extern "C" fn(&Object, Sel) -> u32 = doubled_foo;
+        unsafe {
+            extension.add_method(sel!(doubledFoo), doubled_foo);
+            let err = extension.try_add_method(sel!(doubledFoo), doubled_foo).err();
+            assert!(err == Some(DeclareError::DuplicateMethod(sel!(doubledFoo))));
+            extension.add_class_method_with_closure(sel!(extensionNumber),
+                |_: &Class| -> u32 { 9 });
+        }
+        extension.add_protocol(proto);
+        assert!(cls.conforms_to(proto));
+
+        unsafe {
+            let obj: *mut Object = msg_send![cls, new];
+            let _: () = msg_send![obj, setFoo:4u32];
+            let result: u32 = msg_send![obj, doubledFoo];
+            assert!(result == 8);
+            let _: () = msg_send![obj, release];
+
+            let result: u32 = msg_send![cls, extensionNumber];
+            assert!(result == 9);
+        }
+    }
+
+    #[test]
+    fn test_extension_closure_replaced() {
+        let superclass = test_utils::custom_class();
+        let cls = ClassDecl::new("ExtendedClosureClass", superclass).unwrap()
+            .register();
+        let state = Arc::new(());
+        let captured = state.clone();
+        unsafe {
+            ClassExtension::new(cls).add_class_method_with_closure(sel!(state),
+                move |_: &Class| Arc::strong_count(&captured) as u32);
+            let result: u32 = msg_send![cls, state];
+            assert!(result == 2);
+
+            replace_class_method_with_closure(cls, sel!(state), |_: &Class| 0u32);
+        }
+        assert!(Arc::strong_count(&state) == 1);
+    }
+
+    #[test]
+    fn test_register_checked() {
+        let superclass = test_utils::custom_class();
+        let proto = test_utils::custom_subprotocol();
+
+        let mut decl = ClassDecl::new("UncheckedProtocolClass", superclass).unwrap();
+        decl.add_protocol(proto);
+        let err = decl.register_checked().err();
+        assert!(err == Some(DeclareError::MissingProtocolMethod {
+            protocol: "CustomSubProtocol".to_owned(),
+            sel: sel!(calculateFoo:),
+            is_instance: true,
+        }));
+
+        let mut decl = ClassDecl::new("UncheckedOverrideClass", superclass).unwrap();
+        unsafe {
+            decl.add_method_with_closure(sel!(foo), |_: &Object| -> u64 { 0 });
+        }
+        let err = decl.register_checked().err();
+        assert!(err == Some(DeclareError::OverrideMismatch {
+            sel: sel!(foo),
+            inherited: "I@:".to_owned(),
+            actual: "Q@:".to_owned(),
+        }));
+
+        // Qualifiers like const don't make signatures differ
+        let mut decl = ClassDecl::new("ConstStringClass", superclass).unwrap();
+        unsafe {
+            decl.add_method_with_closure(sel!(UTF8String),
+                |_: &Object| -> *const c_char { ptr::null() });
+        }
+        let base = decl.register_checked().unwrap();
+        let mut decl = ClassDecl::new("MutableStringClass", base).unwrap();
+        unsafe {
+            decl.add_method_with_closure(sel!(UTF8String),
+                |_: &Object| -> *mut c_char { ptr::null_mut() });
+        }
+        assert!(decl.register_checked().is_ok());
+
+        let mut decl = ClassDecl::new("CheckedClass", superclass).unwrap();
+        decl.add_protocol(proto);
+        unsafe {
+            decl.add_method_with_closure(sel!(foo), |_: &Object| -> u32 { 0 });
+            decl.add_method_with_closure(sel!(calculateFoo:),
+                |_: &Object, foo: u32| -> u32 { foo });
+        }
+        assert!(decl.register_checked().is_ok());
+    }
+
+    #[test]
+    fn test_get_or_register() {
+        let superclass = test_utils::custom_class();
+        let declared = Cell::new(0);
+        let declare = |decl: &mut ClassDecl| {
+            declared.set(declared.get() + 1);
+            decl.add_ivar::<u32>("_number");
+        };
+        let cls = ClassDecl::get_or_register("SharedClass", superclass, declare).unwrap();
+        assert!(cls.instance_variable("_number").is_some());
+        assert!(declared.get() == 1);
+        // The declaration is repeated to check that it matches the class
+        let again = ClassDecl::get_or_register("SharedClass", superclass, declare).unwrap();
+        assert!(ptr::eq(cls, again));
+        assert!(declared.get() == 2);
+
+        let err = ClassDecl::get_or_register("SharedClass", cls, declare).err();
+        assert!(err == Some(DeclareError::IncompatibleClass("SharedClass".to_owned())));
+        let err = ClassDecl::get_or_register("SharedClass", superclass, |decl| {
+            decl.add_ivar::<u64>("_number");
+        }).err();
+        assert!(err == Some(DeclareError::IncompatibleClass("SharedClass".to_owned())));
+        let err = ClassDecl::get_or_register("SharedClass", superclass, |decl| {
+            decl.add_ivar::<u32>("_number");
+            unsafe {
+                decl.add_method_with_closure(sel!(number), |_: &Object| -> u32 { 0 });
+            }
+        }).err();
+        assert!(err == Some(DeclareError::IncompatibleClass("SharedClass".to_owned())));
+    }
+
+    #[test]
+    fn test_new_unique() {
+        let superclass = test_utils::custom_class();
+        let first = ClassDecl::new_unique("UniqueClass", superclass).unwrap().register();
+        let second = ClassDecl::new_unique("UniqueClass", superclass).unwrap().register();
+        assert!(first.name().starts_with("UniqueClass"));
+        assert!(first.name() != second.name());
+    }
+
+    #[test]
+    fn test_register_scoped() {
+        let superclass = test_utils::custom_class();
+        let decl = ClassDecl::new("ScopedTestClass", superclass).unwrap();
+        let cls = unsafe { decl.register_scoped() };
+        assert!(Class::get("ScopedTestClass").is_some());
+
+        let obj: *mut Object = unsafe { msg_send![&*cls, new] };
+        assert!(cls.live_instances() == 1);
+        // Can't be disposed while an instance is alive
+        let cls = match cls.dispose() {
+            Err(cls) => cls,
+            Ok(()) => panic!("Disposed a class with a live instance"),
+        };
+        let _: () = unsafe { msg_send![obj, release] };
+        assert!(cls.live_instances() == 0);
+        assert!(cls.dispose().is_ok());
+        assert!(Class::get("ScopedTestClass").is_none());
+
+        // The name can be declared again
+        let decl = ClassDecl::new("ScopedTestClass", superclass).unwrap();
+        drop(unsafe { decl.register_scoped() });
+    }
+
+    #[test]
+    fn test_register_scoped_nsobject() {
+        let superclass = match Class::get("NSObject") {
+            Some(superclass) => superclass,
+            // Test with the Objective-C memory management of NSObject
+            None => return,
+        };
+        let decl = ClassDecl::new_unique("ScopedObject", superclass).unwrap();
+        let cls = unsafe { decl.register_scoped() };
+
+        let obj: *mut Object = unsafe { msg_send![&*cls, new] };
+        assert!(cls.live_instances() == 1);
+        let cls = match cls.dispose() {
+            Err(cls) => cls,
+            Ok(()) => panic!("Disposed a class with a live instance"),
+        };
+        let _: () = unsafe { msg_send![obj, release] };
+        assert!(cls.live_instances() == 0);
+        assert!(cls.dispose().is_ok());
+    }
+
+    #[test]
+    fn test_synthesize_property() {
+        let superclass = test_utils::custom_class();
+        let mut decl = ClassDecl::new("PropertyClass", superclass).unwrap();
+        let attributes = PropertyAttributes::new().nonatomic();
+        decl.synthesize_property::<u32>("count", &attributes);
+        decl.synthesize_property::<u32>("limit",
+            &attributes.clone().readonly().ivar("_max"));
+        let err = decl.try_synthesize_property::<u32>("total",
+            &attributes.clone().semantics(PropertySemantics::Retain)).err();
+        assert!(err == Some(DeclareError::InvalidProperty("total".to_owned())));
+        let err = decl.try_synthesize_property::<u32>("total",
+            &PropertyAttributes::new()).err();
+        assert!(err == Some(DeclareError::InvalidProperty("total".to_owned())));
+        let err = decl.try_add_property::<u32>("count", &attributes).err();
+        assert!(err == Some(DeclareError::DuplicateProperty("count".to_owned())));
+
+        // Conflicts are detected before anything is added
+        let err = decl.try_synthesize_property::<u32>("max", &attributes).err();
+        assert!(err == Some(DeclareError::DuplicateIvar("_max".to_owned())));
+        let err = decl.try_synthesize_property::<u32>("other",
+            &attributes.clone().getter(sel!(count))).err();
+        assert!(err == Some(DeclareError::DuplicateMethod(sel!(count))));
+        let err = decl.try_synthesize_property::<u32>("other",
+            &attributes.clone().ivar("_count")).err();
+        assert!(err == Some(DeclareError::DuplicateIvar("_count".to_owned())));
+        let cls = decl.register();
+        assert!(cls.property("total").is_none());
+        assert!(cls.property("other").is_none());
+        assert!(cls.instance_variable("_total").is_none());
+        assert!(cls.instance_variable("_other").is_none());
+        assert!(cls.instance_method(sel!(other)).is_none());
+
+        assert!(cls.property("count").unwrap().attributes() == "TI,N,V_count");
+        assert!(cls.property("limit").unwrap().attributes() == "TI,R,N,V_max");
+        assert!(cls.instance_method(sel!(setLimit:)).is_none());
+
+        unsafe {
+            let obj: *mut Object = msg_send![cls, new];
+            let _: () = msg_send![obj, setCount:5u32];
+            let count: u32 = msg_send![obj, count];
+            assert!(count == 5);
+            assert!(*(*obj).get_ivar::<u32>("_count") == 5);
+            let _: () = msg_send![obj, release];
+        }
+    }
+
+    #[test]
+    fn test_synthesize_retained_property() {
+        let superclass = match Class::get("NSObject") {
+            Some(superclass) => superclass,
+            // Retaining objects requires NSObject
+            None => return,
+        };
+        let mut decl = ClassDecl::new("RetainedPropertyClass", superclass).unwrap();
+        let attributes = PropertyAttributes::new()
+            .semantics(PropertySemantics::Retain)
+            .nonatomic()
+            .setter(sel!(replaceObject:));
+        decl.synthesize_property::<*mut Object>("object", &attributes);
+        let cls = decl.register();
+        assert!(cls.property("object").unwrap().attributes() ==
+            "T@,&,N,SreplaceObject:,V_object");
+
+        unsafe {
+            let obj: *mut Object = msg_send![cls, new];
+            let value: *mut Object = msg_send![superclass, new];
+            let _: () = msg_send![obj, replaceObject:value];
+            let _: () = msg_send![value, release];
+            let result: *mut Object = msg_send![obj, object];
+            assert!(result == value);
+            let retain_count: usize = msg_send![value, retainCount];
+            assert!(retain_count == 1);
+            // Releases the value too
+            let _: () = msg_send![obj, release];
+        }
+    }
+
+    #[test]
+    fn test_synthesize_weak_property() {
+        let superclass = match Class::get("NSObject") {
+            Some(superclass) => superclass,
+            // Weak references require NSObject
+            None => return,
+        };
+        let mut decl = ClassDecl::new("WeakPropertyClass", superclass).unwrap();
+        let attributes = PropertyAttributes::new()
+            .semantics(PropertySemantics::Weak)
+            .nonatomic();
+        decl.synthesize_property::<*mut Object>("object", &attributes);
+        let cls = decl.register();
+
+        unsafe {
+            let value: *mut Object = msg_send![superclass, new];
+            let owner: *mut Object = msg_send![cls, new];
+            let _: () = msg_send![owner, setObject:value];
+            let obj: *mut Object = msg_send![cls, new];
+            let _: () = msg_send![obj, setObject:value];
+
+            // Freeing the owner first must unregister its weak reference, or
+            // the runtime would clear it in freed memory when the value is
+            // deallocated
+            let _: () = msg_send![owner, release];
+            let _: () = msg_send![value, release];
+            let result: *mut Object = msg_send![obj, object];
+            assert!(result.is_null());
+            let _: () = msg_send![obj, release];
+        }
+    }
+
+    #[test]
+    fn test_protocol_decl_dropped() {
+        let mut decl = ProtocolDecl::new("DroppedProtocol").unwrap();
+        decl.add_method_description::<(), u32>(sel!(count), true);
+        decl.add_protocol(test_utils::custom_protocol());
+        drop(decl);
+        assert!(Protocol::get("DroppedProtocol").is_none());
+
+        // The name can be declared again
+        let decl = ProtocolDecl::new("DroppedProtocol").unwrap();
+        let proto = decl.register();
+        assert!(Protocol::get("DroppedProtocol") == Some(proto));
+    }
+
+    #[test]
+    fn test_protocol_property() {
+        let mut decl = ProtocolDecl::new("PropertyProtocol").unwrap();
+        decl.add_property::<u32>("count", &PropertyAttributes::new().nonatomic(), true);
+        let proto = decl.register();
+        let property = proto.property("count", true).unwrap();
+        assert!(property.name() == "count");
+        assert!(property.attributes() == "TI,N");
+        assert!(proto.property("count", false).is_none());
+    }
+}
@@ -0,0 +1,105 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+
+use exception::{Exception, self};
+
+/**
+What happens when a Rust panic reaches the boundary of a method called from
+Objective-C, where it must not unwind any further.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum PanicPolicy<R> {
+    /// Print the panic message and abort the process.
+    Abort,
+    /// Return the given value from the method.
+    Return(R),
+    /// Raise an `NSException` named `RustPanic` whose reason is the panic
+    /// message, or abort if `NSException` isn't available.
+    ///
+    /// The exception can only unwind out of closures added with
+    /// `ClassDecl::add_method_catching` and functions declared
+    /// `extern "C-unwind"`; it aborts the process when it reaches a function
+    /// declared `extern "C"`.
+    RaiseException,
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_owned()
+    }
+}
+
+fn abort_with(message: &str) -> ! {
+    eprintln!("Panic in Objective-C method, aborting: {}", message);
+    process::abort()
+}
+
+/// Raises an `NSException` for a panic with the given message.
+unsafe fn raise_panic(message: &str) -> ! {
+    match Exception::new("RustPanic", message) {
+        Some(exception) => exception::throw(exception),
+        None => abort_with(message),
+    }
+}
+
+/**
+Calls the given closure, handling a panic according to `policy` rather than
+letting it unwind further.
+
+This is meant to wrap the body of functions implementing Objective-C methods,
+so that panics don't unwind through `objc_msgSend`:
+
+``` no_run
+# #[macro_use] extern crate objc;
+# use objc::declare::{PanicPolicy, catch_panic};
+# use objc::runtime::{Object, Sel};
+# fn main() {
+extern "C-unwind" fn my_number_get(this: &Object, _cmd: Sel) -> u32 {
+    catch_panic(&PanicPolicy::RaiseException, || {
+        unsafe { *this.get_ivar("_number") }
+    })
+}
+# }
+```
+*/
+pub fn catch_panic<R, F>(policy: &PanicPolicy<R>, f: F) -> R
+        where F: FnOnce() -> R, R: Clone {
+    let message = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(value) => return value,
+        Err(payload) => panic_message(&*payload),
+    };
+    match *policy {
+        PanicPolicy::Abort => abort_with(&message),
+        PanicPolicy::Return(ref value) => value.clone(),
+        PanicPolicy::RaiseException => unsafe { raise_panic(&message) },
+    }
+}
+
+/// Calls the given closure, aborting the process if it panics.
+pub fn abort_on_panic<R, F>(f: F) -> R where F: FnOnce() -> R {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(value) => value,
+        Err(payload) => abort_with(&panic_message(&*payload)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PanicPolicy, catch_panic};
+
+    #[test]
+    fn test_catch_panic() {
+        let result = catch_panic(&PanicPolicy::Return(7u32), || 3u32);
+        assert!(result == 3);
+
+        let result = catch_panic(&PanicPolicy::Return(7u32), || -> u32 {
+            panic!("Failed")
+        });
+        assert!(result == 7);
+    }
+}
@@ -0,0 +1,373 @@
+use std::ffi::CString;
+use std::mem;
+use std::os::raw::c_uint;
+use std::ptr;
+
+use runtime::{BOOL, NO, Object, PropertyAttribute, Sel, YES, self};
+use {Encode, Encoding};
+use super::{ClassDecl, DeclareError, IvarCell, ProtocolDecl, c_name, check_args};
+use super::closure::add_closure_method;
+use super::rust_ivar::RustIvar;
+
+/// The memory management semantics of a property.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PropertySemantics {
+    /// The value is assigned without being retained.
+    Assign,
+    /// The object is retained while it's the value of the property.
+    Retain,
+    /// A copy of the object is retained when the property is set.
+    Copy,
+    /// The object isn't retained, and the property becomes nil when it's
+    /// deallocated.
+    Weak,
+}
+
+/**
+The attributes of a declared property other than its type, built by chaining
+methods from `PropertyAttributes::new()`:
+
+``` no_run
+# #[macro_use] extern crate objc;
+# use objc::declare::{PropertyAttributes, PropertySemantics};
+# fn main() {
+let attributes = PropertyAttributes::new()
+    .semantics(PropertySemantics::Copy)
+    .nonatomic()
+    .getter(sel!(isEnabled));
+# }
+```
+
+By default, a property is atomic, read-write and assigned, with a getter
+named after the property and a setter named `set` followed by the capitalized
+name of the property.
+*/
+#[derive(Clone, Debug)]
+pub struct PropertyAttributes {
+    readonly: bool,
+    nonatomic: bool,
+    semantics: PropertySemantics,
+    getter: Option<Sel>,
+    setter: Option<Sel>,
+    ivar: Option<String>,
+    dynamic: bool,
+}
+
+impl PropertyAttributes {
+    /// Constructs the default attributes of a property.
+    pub fn new() -> PropertyAttributes {
+        PropertyAttributes {
+            readonly: false,
+            nonatomic: false,
+            semantics: PropertySemantics::Assign,
+            getter: None,
+            setter: None,
+            ivar: None,
+            dynamic: false,
+        }
+    }
+
+    /// Makes the property read-only, without a setter.
+    pub fn readonly(mut self) -> PropertyAttributes {
+        self.readonly = true;
+        self
+    }
+
+    /// Makes the property nonatomic.
+    pub fn nonatomic(mut self) -> PropertyAttributes {
+        self.nonatomic = true;
+        self
+    }
+
+    /// Sets the memory management semantics of the property.
+    pub fn semantics(mut self, semantics: PropertySemantics) -> PropertyAttributes {
+        self.semantics = semantics;
+        self
+    }
+
+    /// Sets the selector of the property's getter.
+    pub fn getter(mut self, sel: Sel) -> PropertyAttributes {
+        self.getter = Some(sel);
+        self
+    }
+
+    /// Sets the selector of the property's setter.
+    pub fn setter(mut self, sel: Sel) -> PropertyAttributes {
+        self.setter = Some(sel);
+        self
+    }
+
+    /// Sets the name of the ivar backing the property.
+    pub fn ivar(mut self, name: &str) -> PropertyAttributes {
+        self.ivar = Some(name.to_owned());
+        self
+    }
+
+    /// Marks the property as dynamic, meaning its accessors are provided at
+    /// runtime rather than synthesized.
+    pub fn dynamic(mut self) -> PropertyAttributes {
+        self.dynamic = true;
+        self
+    }
+
+    fn getter_sel(&self, name: &str) -> Sel {
+        self.getter.unwrap_or_else(|| Sel::register(name))
+    }
+
+    fn setter_sel(&self, name: &str) -> Sel {
+        self.setter.unwrap_or_else(|| {
+            let mut chars = name.chars();
+            let first = chars.next().map(|c| c.to_uppercase().collect::<String>());
+            let rest: String = chars.collect();
+            Sel::register(&format!("set{}{}:", first.unwrap_or_default(), rest))
+        })
+    }
+
+    /// Returns the runtime's attributes for a property of the given type
+    /// with these attributes, in the order the compiler emits them.
+    fn attribute_list(&self, encoding: &Encoding) -> Result<Vec<(CString, CString)>, DeclareError> {
+        let mut attributes = vec![("T", encoding.as_str().to_owned())];
+        if self.readonly {
+            attributes.push(("R", String::new()));
+        }
+        match self.semantics {
+            PropertySemantics::Assign => (),
+            PropertySemantics::Retain => attributes.push(("&", String::new())),
+            PropertySemantics::Copy => attributes.push(("C", String::new())),
+            PropertySemantics::Weak => attributes.push(("W", String::new())),
+        }
+        if self.nonatomic {
+            attributes.push(("N", String::new()));
+        }
+        if let Some(getter) = self.getter {
+            attributes.push(("G", getter.name().to_owned()));
+        }
+        if let Some(setter) = self.setter {
+            attributes.push(("S", setter.name().to_owned()));
+        }
+        if self.dynamic {
+            attributes.push(("D", String::new()));
+        }
+        if let Some(ref ivar) = self.ivar {
+            attributes.push(("V", ivar.clone()));
+        }
+        attributes.into_iter().map(|(name, value)| {
+            Ok((CString::new(name).unwrap(), c_name(&value)?))
+        }).collect()
+    }
+}
+
+impl Default for PropertyAttributes {
+    fn default() -> PropertyAttributes {
+        PropertyAttributes::new()
+    }
+}
+
+/// Calls `f` with the runtime's representation of the given attributes.
+fn with_attributes<F, R>(attributes: &[(CString, CString)], f: F) -> R
+        where F: FnOnce(*const PropertyAttribute, c_uint) -> R {
+    let attributes: Vec<PropertyAttribute> = attributes.iter().map(|(name, value)| {
+        PropertyAttribute { name: name.as_ptr(), value: value.as_ptr() }
+    }).collect();
+    f(attributes.as_ptr(), attributes.len() as c_uint)
+}
+
+unsafe fn release_ivar(ptr: *mut u8) {
+    let obj = *(ptr as *mut *mut Object);
+    if !obj.is_null() {
+        let _: () = msg_send![obj, release];
+    }
+}
+
+unsafe fn destroy_weak_ivar(ptr: *mut u8) {
+    runtime::objc_storeWeak(ptr as *mut *mut Object, ptr::null_mut());
+}
+
+impl ClassDecl {
+    /// Adds a property of type `T` with the given name and attributes to
+    /// self, without adding its accessors or ivar.
+    /// Panics if the property wasn't successfully added.
+    pub fn add_property<T>(&mut self, name: &str, attributes: &PropertyAttributes)
+            where T: Encode {
+        self.try_add_property::<T>(name, attributes)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Adds a property of type `T` with the given name and attributes to
+    /// self, without adding its accessors or ivar.
+    /// Returns an error if the property wasn't successfully added.
+    pub fn try_add_property<T>(&mut self, name: &str,
+            attributes: &PropertyAttributes) -> Result<(), DeclareError>
+            where T: Encode {
+        let c_name = c_name(name)?;
+        let attributes = attributes.attribute_list(&T::encode())?;
+        let success = with_attributes(&attributes, |attributes, count| unsafe {
+            runtime::class_addProperty(self.cls, c_name.as_ptr(), attributes, count)
+        });
+        if success != NO {
+            Ok(())
+        } else if unsafe { (*self.cls).property(name).is_some() } {
+            Err(DeclareError::DuplicateProperty(name.to_owned()))
+        } else {
+            Err(DeclareError::RuntimeRefused(format!("add property {}", name)))
+        }
+    }
+
+    /// Adds a property of type `T` with the given name and attributes to
+    /// self, along with an ivar backing it and its accessor methods.
+    /// Panics if the property wasn't successfully synthesized.
+    pub fn synthesize_property<T>(&mut self, name: &str,
+            attributes: &PropertyAttributes)
+            where T: Encode + Copy + 'static {
+        self.try_synthesize_property::<T>(name, attributes)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /**
+    Adds a property of type `T` with the given name and attributes to self,
+    along with an ivar backing it and its accessor methods.
+    Returns an error if the property wasn't successfully synthesized.
+
+    The ivar is named after the property with a leading underscore, unless
+    another name is given in the attributes, and is an `IvarCell<T>`.
+    A getter is added, as well as a setter unless the property is read-only.
+
+    Properties that retain, copy or weakly reference their value must have an
+    object type like `*mut Object`. The value is released, or the weak
+    reference is cleared, when an instance receives `-dealloc`, by the same
+    override that drops the ivars added with `add_rust_ivar`.
+
+    The synthesized accessors don't synchronize with each other, so the
+    property must be nonatomic; otherwise `DeclareError::InvalidProperty` is
+    returned. The property, ivar and accessors are checked before any of them
+    is added, so self is left unchanged if they conflict with existing ones.
+    */
+    pub fn try_synthesize_property<T>(&mut self, name: &str,
+            attributes: &PropertyAttributes) -> Result<(), DeclareError>
+            where T: Encode + Copy + 'static {
+        let ivar_name = attributes.ivar.clone()
+            .unwrap_or_else(|| format!("_{}", name));
+        let attributes = attributes.clone().ivar(&ivar_name);
+        let getter = attributes.getter_sel(name);
+        let setter = if attributes.readonly { None } else { Some(attributes.setter_sel(name)) };
+        self.check_synthesized_property::<T>(name, &attributes, &ivar_name,
+            getter, setter)?;
+
+        if attributes.semantics == PropertySemantics::Assign {
+            let ivar = self.try_add_ivar::<IvarCell<T>>(&ivar_name)?;
+            let getter_ivar = ivar.clone();
+            unsafe {
+                add_closure_method(self.cls, getter, move |this: &Object| -> T {
+                    getter_ivar.get(this).get()
+                })?;
+                if let Some(setter) = setter {
+                    add_closure_method(self.cls, setter, move |this: &Object, value: T| {
+                        ivar.get(this).set(value);
+                    })?;
+                }
+            }
+        } else {
+            let ivar = self.try_add_ivar::<IvarCell<*mut Object>>(&ivar_name)?;
+            let semantics = attributes.semantics;
+            self.rust_ivars.push(if semantics == PropertySemantics::Weak {
+                RustIvar::with_destructor(&ivar_name, destroy_weak_ivar)
+            } else {
+                RustIvar::with_destructor(&ivar_name, release_ivar)
+            });
+
+            let getter_ivar = ivar.clone();
+            unsafe {
+                add_closure_method(self.cls, getter, move |this: &Object| -> *mut Object {
+                    let value = getter_ivar.get(this);
+                    if semantics != PropertySemantics::Weak {
+                        return value.get();
+                    }
+                    let obj = runtime::objc_loadWeakRetained(value.as_ptr());
+                    if obj.is_null() { obj } else { msg_send![obj, autorelease] }
+                })?;
+                if let Some(setter) = setter {
+                    add_closure_method(self.cls, setter, move |this: &Object, obj: *mut Object| {
+                        let value = ivar.get(this);
+                        let obj: *mut Object = match semantics {
+                            PropertySemantics::Weak => {
+                                runtime::objc_storeWeak(value.as_ptr(), obj);
+                                return;
+                            }
+                            _ if obj.is_null() => obj,
+                            PropertySemantics::Copy => msg_send![obj, copy],
+                            _ => msg_send![obj, retain],
+                        };
+                        let previous = value.replace(obj);
+                        if !previous.is_null() {
+                            let _: () = msg_send![previous, release];
+                        }
+                    })?;
+                }
+            }
+        }
+
+        self.try_add_property::<T>(name, &attributes)
+    }
+
+    /// Checks that a property can be synthesized without conflicting with
+    /// the existing properties, ivars and methods of self.
+    fn check_synthesized_property<T>(&self, name: &str,
+            attributes: &PropertyAttributes, ivar_name: &str, getter: Sel,
+            setter: Option<Sel>) -> Result<(), DeclareError> where T: Encode {
+        let is_object = T::encode() == <*mut Object>::encode() &&
+            mem::size_of::<T>() == mem::size_of::<*mut Object>();
+        if !attributes.nonatomic ||
+                (attributes.semantics != PropertySemantics::Assign && !is_object) {
+            return Err(DeclareError::InvalidProperty(name.to_owned()));
+        }
+        c_name(name)?;
+        attributes.attribute_list(&T::encode())?;
+
+        let cls = unsafe { &*self.cls };
+        if cls.properties().iter().any(|property| property.name() == name) {
+            return Err(DeclareError::DuplicateProperty(name.to_owned()));
+        }
+        if cls.instance_variable(ivar_name).is_some() {
+            return Err(DeclareError::DuplicateIvar(ivar_name.to_owned()));
+        }
+
+        check_args(getter, &[])?;
+        if let Some(setter) = setter {
+            check_args(setter, &[T::encode()])?;
+        }
+        let methods = cls.instance_methods();
+        for sel in Some(getter).into_iter().chain(setter) {
+            if methods.iter().any(|method| method.name() == sel) {
+                return Err(DeclareError::DuplicateMethod(sel));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ProtocolDecl {
+    /// Adds a declaration of an instance property of type `T` with the given
+    /// name and attributes to self.
+    /// Panics if the name contains a nul byte.
+    pub fn add_property<T>(&mut self, name: &str,
+            attributes: &PropertyAttributes, is_required: bool)
+            where T: Encode {
+        self.try_add_property::<T>(name, attributes, is_required)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Adds a declaration of an instance property of type `T` with the given
+    /// name and attributes to self.
+    /// Returns an error if the name contains a nul byte.
+    pub fn try_add_property<T>(&mut self, name: &str,
+            attributes: &PropertyAttributes, is_required: bool)
+            -> Result<(), DeclareError> where T: Encode {
+        let c_name = c_name(name)?;
+        let attributes = attributes.attribute_list(&T::encode())?;
+        with_attributes(&attributes, |attributes, count| unsafe {
+            runtime::protocol_addProperty(self.proto, c_name.as_ptr(),
+                attributes, count, is_required as BOOL, YES);
+        });
+        Ok(())
+    }
+}
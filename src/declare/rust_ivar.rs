@@ -0,0 +1,271 @@
+use std::ffi::CString;
+use std::mem::{self, MaybeUninit};
+use std::os::raw::c_void;
+use std::ptr;
+use std::sync::{Arc, OnceLock};
+
+use encode;
+use runtime::{Class, Imp, NO, Object, Sel, self};
+use Encoding;
+use super::{ClassDecl, DeclareError, c_name, log2_align_of};
+use super::closure::override_closure_method;
+
+/// The storage of an ivar holding a Rust value, which tracks whether the
+/// value has been initialized so that it's only dropped if it was.
+#[repr(C)]
+struct Storage<T> {
+    initialized: bool,
+    value: MaybeUninit<T>,
+}
+
+/// Returns the encoding of the storage of a Rust ivar of type `T`, an opaque
+/// byte array of the right size.
+fn storage_encoding<T>() -> Encoding {
+    encode::from_str(&format!("[{}C]", mem::size_of::<Storage<T>>()))
+}
+
+/// An ivar holding a Rust value, with the functions for initializing and
+/// dropping its storage.
+pub struct RustIvar {
+    name: String,
+    construct: Box<dyn Fn(*mut u8) + Send + Sync>,
+    destruct: unsafe fn(*mut u8),
+}
+
+unsafe fn drop_storage<T>(ptr: *mut u8) {
+    let storage = &mut *(ptr as *mut Storage<T>);
+    if storage.initialized {
+        storage.initialized = false;
+        ptr::drop_in_place(storage.value.as_mut_ptr());
+    }
+}
+
+impl RustIvar {
+    fn new<T, F>(name: &str, init: F) -> RustIvar
+            where T: Send + 'static, F: Fn() -> T + Send + Sync + 'static {
+        RustIvar {
+            name: name.to_owned(),
+            construct: Box::new(move |ptr| unsafe {
+                let storage = &mut *(ptr as *mut Storage<T>);
+                storage.value = MaybeUninit::new(init());
+                storage.initialized = true;
+            }),
+            destruct: drop_storage::<T>,
+        }
+    }
+
+    /// Creates an entry for an ivar that is left zeroed when an instance is
+    /// allocated and cleaned up by `destruct` when it's deallocated.
+    pub fn with_destructor(name: &str, destruct: unsafe fn(*mut u8)) -> RustIvar {
+        RustIvar {
+            name: name.to_owned(),
+            construct: Box::new(|_| ()),
+            destruct,
+        }
+    }
+}
+
+/// Returns the offsets of the given ivars of `cls`.
+fn ivar_offsets(cls: &Class, ivars: &[RustIvar]) -> Vec<isize> {
+    ivars.iter().map(|ivar| {
+        cls.instance_variable(&ivar.name)
+            .expect("Rust ivar not found on its class")
+            .offset()
+    }).collect()
+}
+
+/// Returns the implementation of `sel` that `cls` declares itself, if any.
+unsafe fn own_implementation(cls: *const Class, sel: Sel) -> Option<Imp> {
+    (*cls).instance_methods().iter()
+        .find(|method| method.name() == sel)
+        .map(|method| method.implementation())
+}
+
+/// Overrides `+allocWithZone:` and `-dealloc` of `cls` to initialize the
+/// given ivars when instances of `cls` are allocated and drop them when
+/// they're deallocated.
+///
+/// The overrides continue with the implementation `cls` declared itself, if
+/// any, or else with the one inherited from its superclass. The ivars are
+/// dropped before the rest of `-dealloc` runs.
+pub unsafe fn add_rust_ivar_methods(cls: *mut Class, ivars: Vec<RustIvar>)
+        -> Result<(), DeclareError> {
+    struct Ivars {
+        cls: usize,
+        ivars: Vec<RustIvar>,
+        offsets: OnceLock<Vec<isize>>,
+    }
+
+    impl Ivars {
+        fn class(&self) -> &Class {
+            unsafe { &*(self.cls as *const Class) }
+        }
+
+        fn each<F>(&self, this: *mut Object, f: F) where F: Fn(&RustIvar, *mut u8) {
+            // Ivar offsets are final once the class has been registered
+            let offsets = self.offsets.get_or_init(|| {
+                ivar_offsets(self.class(), &self.ivars)
+            });
+            let base = this as *mut u8;
+            for (ivar, &offset) in self.ivars.iter().zip(offsets) {
+                f(ivar, unsafe { base.offset(offset) });
+            }
+        }
+    }
+
+    let ivars = Arc::new(Ivars {
+        cls: cls as usize,
+        ivars,
+        offsets: OnceLock::new(),
+    });
+
+    // Subclasses inherit these overrides, so the next implementation must be
+    // looked up from the declaring class rather than the receiver's class
+    let metaclass = (*cls).metaclass() as *const Class as *mut Class;
+    let own_alloc = own_implementation(metaclass, sel!(allocWithZone:));
+    let alloc_ivars = ivars.clone();
+    override_closure_method(metaclass, sel!(allocWithZone:),
+        move |this: &Class, zone: *mut c_void| -> *mut Object {
+            let sel = sel!(allocWithZone:);
+            let next = own_alloc.or_else(|| alloc_ivars.class().superclass().map(|sup| unsafe {
+                runtime::class_getMethodImplementation(sup.metaclass(), sel)
+            }));
+            let obj = unsafe {
+                match next {
+                    Some(imp) => {
+                        let imp: unsafe extern "C" fn(&Class, Sel, *mut c_void) -> *mut Object =
+                            mem::transmute(imp);
+                        imp(this, sel, zone)
+                    }
+                    None => runtime::class_createInstance(this, 0),
+                }
+            };
+            if !obj.is_null() {
+                alloc_ivars.each(obj, |ivar, ptr| (ivar.construct)(ptr));
+            }
+            obj
+        })?;
+
+    let own_dealloc = own_implementation(cls, sel!(dealloc));
+    override_closure_method(cls, sel!(dealloc),
+        move |this: &mut Object| {
+            let this = this as *mut Object;
+            ivars.each(this, |ivar, ptr| unsafe { (ivar.destruct)(ptr) });
+            let sel = sel!(dealloc);
+            let next = own_dealloc.or_else(|| ivars.class().superclass().map(|sup| unsafe {
+                runtime::class_getMethodImplementation(sup, sel)
+            }));
+            unsafe {
+                match next {
+                    Some(imp) => {
+                        let imp: unsafe extern "C" fn(*mut Object, Sel) = mem::transmute(imp);
+                        imp(this, sel);
+                    }
+                    None => {
+                        runtime::object_dispose(this);
+                    }
+                }
+            }
+        })?;
+    Ok(())
+}
+
+impl ClassDecl {
+    /// Adds an ivar holding a Rust value of type `T` with the provided name
+    /// to self. The ivar is initialized with `T::default()` when an instance
+    /// is allocated, and dropped when the instance is deallocated.
+    /// Panics if the ivar wasn't successfully added.
+    pub fn add_rust_ivar<T>(&mut self, name: &str)
+            where T: Default + Send + 'static {
+        self.add_rust_ivar_with(name, T::default)
+    }
+
+    /// Adds an ivar holding a Rust value of type `T` with the provided name
+    /// to self. The ivar is initialized with the result of `init` when an
+    /// instance is allocated, and dropped when the instance is deallocated.
+    /// Panics if the ivar wasn't successfully added.
+    pub fn add_rust_ivar_with<T, F>(&mut self, name: &str, init: F)
+            where T: Send + 'static, F: Fn() -> T + Send + Sync + 'static {
+        self.try_add_rust_ivar_with(name, init)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /**
+    Adds an ivar holding a Rust value of type `T` with the provided name to
+    self. The ivar is initialized with the result of `init` when an instance
+    is allocated, and dropped when the instance is deallocated.
+    Returns an error if the ivar wasn't successfully added.
+
+    The value is stored as an opaque byte array, so it isn't visible to
+    Objective-C. It can be accessed with `Object::get_rust_ivar` and
+    `Object::get_mut_rust_ivar`. When the class is registered, it overrides
+    `+allocWithZone:` to initialize its Rust ivars and `-dealloc` to drop
+    them before continuing with the class's own or inherited implementation.
+    Instances created without `+allocWithZone:`, such as with
+    `class_createInstance`, aren't initialized, and instances must be
+    deallocated with `-dealloc` for their Rust ivars to be dropped.
+    */
+    pub fn try_add_rust_ivar_with<T, F>(&mut self, name: &str, init: F)
+            -> Result<(), DeclareError>
+            where T: Send + 'static, F: Fn() -> T + Send + Sync + 'static {
+        let c_name = c_name(name)?;
+        let encoding = CString::new(storage_encoding::<T>().as_str()).unwrap();
+        let size = mem::size_of::<Storage<T>>();
+        let align = log2_align_of::<Storage<T>>();
+        let success = unsafe {
+            runtime::class_addIvar(self.cls, c_name.as_ptr(), size, align,
+                encoding.as_ptr())
+        };
+        if success != NO {
+            self.rust_ivars.push(RustIvar::new(name, init));
+            Ok(())
+        } else if unsafe { (*self.cls).instance_variable(name).is_some() } {
+            Err(DeclareError::DuplicateIvar(name.to_owned()))
+        } else {
+            Err(DeclareError::RuntimeRefused(format!("add ivar {}", name)))
+        }
+    }
+}
+
+impl Object {
+    fn rust_ivar_storage<T>(&self, name: &str) -> *mut Storage<T> {
+        let cls = self.class();
+        let offset = match cls.instance_variable(name) {
+            Some(ivar) => {
+                assert!(ivar.type_encoding() == storage_encoding::<T>(),
+                    "Ivar {} doesn't hold a Rust value of this type", name);
+                ivar.offset()
+            }
+            None => panic!("Ivar {} not found on class {:?}", name, cls),
+        };
+        let self_ptr = self as *const Object as *mut u8;
+        let storage = unsafe { self_ptr.offset(offset) as *mut Storage<T> };
+        assert!(unsafe { (*storage).initialized },
+            "Ivar {} has not been initialized", name);
+        storage
+    }
+
+    /// Returns a reference to the ivar of self with the given name holding
+    /// a Rust value, which was added with `ClassDecl::add_rust_ivar`.
+    /// Panics if self has no such ivar or it hasn't been initialized.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the ivar actually holds a `T`; only its
+    /// size is checked.
+    pub unsafe fn get_rust_ivar<T>(&self, name: &str) -> &T {
+        &*(*self.rust_ivar_storage::<T>(name)).value.as_ptr()
+    }
+
+    /// Returns a mutable reference to the ivar of self with the given name
+    /// holding a Rust value, which was added with `ClassDecl::add_rust_ivar`.
+    /// Panics if self has no such ivar or it hasn't been initialized.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the ivar actually holds a `T`; only its
+    /// size is checked.
+    pub unsafe fn get_mut_rust_ivar<T>(&mut self, name: &str) -> &mut T {
+        &mut *(*self.rust_ivar_storage::<T>(name)).value.as_mut_ptr()
+    }
+}
@@ -0,0 +1,134 @@
+use std::fmt;
+use std::ops::Deref;
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use runtime::Class;
+use super::{ClassDecl, dispose_class};
+
+/// A value held by every instance of a scoped class, which counts them while
+/// it's initialized by `+allocWithZone:` and dropped by `-dealloc`.
+struct LiveInstance {
+    count: Arc<AtomicUsize>,
+}
+
+impl LiveInstance {
+    fn new(count: Arc<AtomicUsize>) -> LiveInstance {
+        count.fetch_add(1, Ordering::SeqCst);
+        LiveInstance { count }
+    }
+}
+
+impl Drop for LiveInstance {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/**
+A registered class that is disposed when dropped, as returned by
+`ClassDecl::register_scoped`.
+
+The class can only be disposed once it has no instances and no subclasses;
+otherwise dropping the `ScopedClass` leaks the class instead, and logs that it
+did. `dispose` can be used to check that it was actually disposed.
+*/
+pub struct ScopedClass {
+    cls: *mut Class,
+    live: Arc<AtomicUsize>,
+    disposed: bool,
+}
+
+impl ScopedClass {
+    /// Returns the number of instances of the class that haven't been
+    /// deallocated yet.
+    pub fn live_instances(&self) -> usize {
+        self.live.load(Ordering::SeqCst)
+    }
+
+    fn has_subclasses(&self) -> bool {
+        Class::classes().iter().any(|cls| {
+            cls.superclass().is_some_and(|superclass| ptr::eq(superclass, self.cls))
+        })
+    }
+
+    fn can_dispose(&self) -> bool {
+        self.live_instances() == 0 && !self.has_subclasses()
+    }
+
+    /// Disposes the class unless it still has instances or subclasses.
+    /// Returns whether it has been disposed.
+    fn try_dispose(&mut self) -> bool {
+        if !self.disposed && self.can_dispose() {
+            unsafe {
+                dispose_class(self.cls);
+            }
+            self.disposed = true;
+        }
+        self.disposed
+    }
+
+    /// Disposes the class, or returns self if it still has instances or
+    /// subclasses.
+    pub fn dispose(mut self) -> Result<(), ScopedClass> {
+        if self.try_dispose() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl Deref for ScopedClass {
+    type Target = Class;
+
+    fn deref(&self) -> &Class {
+        unsafe { &*self.cls }
+    }
+}
+
+impl Drop for ScopedClass {
+    fn drop(&mut self) {
+        if !self.try_dispose() {
+            eprintln!("objc: leaking scoped class {} since it still has instances or subclasses",
+                self.name());
+        }
+    }
+}
+
+impl fmt::Debug for ScopedClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl ClassDecl {
+    /**
+    Registers self like `register`, but returns a `ScopedClass` which
+    disposes the class when it's dropped, for declaring throwaway classes.
+
+    The instances of the class are counted by its overrides of
+    `+allocWithZone:` and `-dealloc`, which are added like those for
+    `add_rust_ivar`, so that it's only disposed once they have all been
+    deallocated. Instances that aren't allocated with `+allocWithZone:` (or
+    `+alloc` and `+new`, which use it) aren't counted.
+
+    # Safety
+
+    The caller must ensure that no other reference to the class outlives the
+    returned `ScopedClass`. This includes references from `Class::get` or
+    `class!`, to its metaclass, methods or ivars, and `IvarRef`s for its ivars.
+    Since the class is registered under its name, registering it with a name
+    from `ClassDecl::new_unique` keeps other code from finding it by name.
+    */
+    pub unsafe fn register_scoped(mut self) -> ScopedClass {
+        let live = Arc::new(AtomicUsize::new(0));
+        let count = live.clone();
+        self.add_rust_ivar_with("_liveInstance", move || {
+            LiveInstance::new(count.clone())
+        });
+        let cls = self.register() as *const Class as *mut Class;
+        ScopedClass { cls, live, disposed: false }
+    }
+}
@@ -2,8 +2,10 @@ use std::ffi::CStr;
 use std::fmt;
 use std::os::raw::{c_char, c_void};
 use std::str;
+use std::sync::atomic;
 use malloc_buf::MallocBuffer;
 
+use block::Block;
 use runtime::{Class, Object, Sel};
 
 const QUALIFIERS: &'static [char] = &[
@@ -108,6 +110,103 @@ pub unsafe fn from_malloc_str(ptr: *mut c_char) -> Encoding {
     Encoding { code: Code::Malloc(buf) }
 }
 
+/// Returns the length of the single type at the start of `code`, which must
+/// not begin with qualifiers.
+fn type_len(code: &str) -> Option<usize> {
+    let mut chars = code.chars();
+    let len = match chars.next()? {
+        '^' => {
+            let pointee = &code[1..];
+            let qualifiers = pointee.len() - pointee.trim_start_matches(QUALIFIERS).len();
+            1 + qualifiers + type_len(&pointee[qualifiers..])?
+        }
+        '@' => match chars.next() {
+            // Extended block encodings may include the signature in brackets
+            Some('?') if code[2..].starts_with('<') => 2 + enclosed_len(&code[2..])?,
+            Some('?') => 2,
+            // Extended object encodings may include the class name in quotes
+            Some('"') => 2 + code[2..].find('"')? + 1,
+            _ => 1,
+        },
+        'b' => 1 + code[1..].len() - code[1..].trim_start_matches(|c: char| c.is_ascii_digit()).len(),
+        '[' | '{' | '(' => enclosed_len(code)?,
+        _ => 1,
+    };
+    Some(len)
+}
+
+/// Returns the length of the bracketed group at the start of `code`.
+fn enclosed_len(code: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut quoted = false;
+    for (i, c) in code.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            _ if quoted => (),
+            '[' | '{' | '(' | '<' => depth += 1,
+            ']' | '}' | ')' | '>' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Splits the first type off of an encoding containing several types, like
+/// a method or block signature, returning the type and the remaining
+/// encoding. Any offset following the type is skipped.
+pub fn split_type(code: &str) -> Option<(&str, &str)> {
+    let qualifiers = code.len() - code.trim_start_matches(QUALIFIERS).len();
+    let len = qualifiers + type_len(&code[qualifiers..])?;
+    let (ty, rest) = code.split_at(len);
+    let rest = rest.trim_start_matches(|c: char| c.is_ascii_digit() || c == '-');
+    Some((ty, rest))
+}
+
+/// Removes the class names, field names and block signatures of extended
+/// encodings, which aren't produced by `Encode` implementations.
+pub fn strip_extended(code: &str) -> String {
+    let mut stripped = String::with_capacity(code.len());
+    let mut quoted = false;
+    let mut depth = 0;
+    for c in code.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            _ if quoted => (),
+            '<' => depth += 1,
+            '>' => depth -= 1,
+            _ if depth > 0 => (),
+            c => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+/// Removes the type qualifiers, like `r` for const, from the start of an
+/// encoding and from the types that its pointers point to. Qualifiers don't
+/// affect the layout of a type, so they're ignored when comparing signatures.
+pub fn strip_qualifiers(code: &str) -> String {
+    let mut stripped = String::with_capacity(code.len());
+    let mut rest = code;
+    loop {
+        rest = rest.trim_start_matches(QUALIFIERS);
+        match rest.find('^') {
+            Some(i) => {
+                stripped.push_str(&rest[..i + 1]);
+                rest = &rest[i + 1..];
+            }
+            None => {
+                stripped.push_str(rest);
+                return stripped;
+            }
+        }
+    }
+}
+
 /// Types that have an Objective-C type encoding.
 ///
 /// Unsafe because Objective-C will make assumptions about the type (like its
@@ -162,6 +261,37 @@ unsafe impl Encode for usize {
     fn encode() -> Encoding { u64::encode() }
 }
 
+// Atomics have the same in-memory representation as the types they wrap
+macro_rules! encode_atomic_impls {
+    ($($atomic:ident : $t:ty,)*) => ($(
+        unsafe impl Encode for atomic::$atomic {
+            fn encode() -> Encoding { <$t>::encode() }
+        }
+    )*);
+}
+
+encode_atomic_impls!(
+    AtomicBool: bool,
+    AtomicI8: i8,
+    AtomicI16: i16,
+    AtomicI32: i32,
+    AtomicIsize: isize,
+    AtomicU8: u8,
+    AtomicU16: u16,
+    AtomicU32: u32,
+    AtomicUsize: usize,
+);
+
+#[cfg(target_has_atomic = "64")]
+encode_atomic_impls!(
+    AtomicI64: i64,
+    AtomicU64: u64,
+);
+
+unsafe impl<T> Encode for atomic::AtomicPtr<T> {
+    fn encode() -> Encoding { <*mut c_void>::encode() }
+}
+
 macro_rules! encode_message_impl {
     ($code:expr, $name:ident) => (
         encode_message_impl!($code, $name,);
@@ -197,6 +327,8 @@ encode_message_impl!("@", Object);
 
 encode_message_impl!("#", Class);
 
+encode_message_impl!("@?", Block, A, R);
+
 /// Types that represent a group of arguments, where each has an Objective-C
 /// type encoding.
 pub trait EncodeArguments {
@@ -227,24 +359,27 @@ macro_rules! encode_args_impl {
     );
 }
 
-encode_args_impl!();
-encode_args_impl!(A);
-encode_args_impl!(A, B);
-encode_args_impl!(A, B, C);
-encode_args_impl!(A, B, C, D);
-encode_args_impl!(A, B, C, D, E);
-encode_args_impl!(A, B, C, D, E, F);
-encode_args_impl!(A, B, C, D, E, F, G);
-encode_args_impl!(A, B, C, D, E, F, G, H);
-encode_args_impl!(A, B, C, D, E, F, G, H, I);
-encode_args_impl!(A, B, C, D, E, F, G, H, I, J);
-encode_args_impl!(A, B, C, D, E, F, G, H, I, J, K);
-encode_args_impl!(A, B, C, D, E, F, G, H, I, J, K, L);
+// Implements the trait for a tuple of the given types and for each of its
+// suffixes, so every arity up to the longest list is covered by one invocation.
+macro_rules! encode_args_impls {
+    () => (
+        encode_args_impl!();
+    );
+    ($t:ident $(, $u:ident)*) => (
+        encode_args_impl!($t $(, $u)*);
+        encode_args_impls!($($u),*);
+    );
+}
+
+encode_args_impls!(A1, A2, A3, A4, A5, A6, A7, A8, A9, A10, A11, A12, A13, A14,
+    A15, A16, A17, A18, A19, A20, A21, A22, A23, A24, A25, A26, A27, A28, A29,
+    A30, A31, A32);
 
 #[cfg(test)]
 mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicUsize};
     use runtime::{Class, Object, Sel};
-    use super::{Encode, Encoding};
+    use super::{Encode, Encoding, split_type, strip_extended, strip_qualifiers};
 
     #[test]
     fn test_encode() {
@@ -256,6 +391,42 @@ mod tests {
         assert!(Sel::encode().as_str() == ":");
     }
 
+    #[test]
+    fn test_encode_atomic() {
+        assert!(AtomicBool::encode() == bool::encode());
+        assert!(AtomicU32::encode().as_str() == "I");
+        assert!(AtomicUsize::encode() == usize::encode());
+        assert!(<AtomicPtr<Object>>::encode().as_str() == "^v");
+    }
+
+    #[test]
+    fn test_split_type() {
+        assert!(split_type("i16@?0i8") == Some(("i", "@?0i8")));
+        assert!(split_type("@?0i8") == Some(("@?", "i8")));
+        assert!(split_type("r^{Foo=ii}8") == Some(("r^{Foo=ii}", "")));
+        assert!(split_type("@\"NSString\"8@") == Some(("@\"NSString\"", "@")));
+        assert!(split_type("@?<v@?i>8") == Some(("@?<v@?i>", "")));
+        assert!(split_type("[4{Foo=\"a\"i}]v") == Some(("[4{Foo=\"a\"i}]", "v")));
+        assert!(split_type("b12i") == Some(("b12", "i")));
+        assert!(split_type("{Foo=i").is_none());
+        assert!(split_type("").is_none());
+    }
+
+    #[test]
+    fn test_strip_extended() {
+        assert!(strip_extended("@\"NSString\"") == "@");
+        assert!(strip_extended("@?<v@?@\"NSError\">") == "@?");
+        assert!(strip_extended("{CGPoint=\"x\"d\"y\"d}") == "{CGPoint=dd}");
+    }
+
+    #[test]
+    fn test_strip_qualifiers() {
+        assert!(strip_qualifiers("r*") == "*");
+        assert!(strip_qualifiers("Vv") == "v");
+        assert!(strip_qualifiers("^r^n{Rect=ii}") == "^^{Rect=ii}");
+        assert!(strip_qualifiers("{Rect=ii}") == "{Rect=ii}");
+    }
+
     #[test]
     fn test_inline_encoding() {
         let enc = unsafe { Encoding::from_str("C") };
@@ -1,12 +1,216 @@
+/*!
+Raising and catching Objective-C exceptions.
+
+Any Objective-C object can be thrown as an exception; it is wrapped in an
+`Exception`, which provides the name, reason and user info of exceptions
+that respond to them, like `NSException`.
+
+Exceptions can be raised with `throw`. With the `"exception"` feature enabled,
+they can be caught around arbitrary code with `catch_exception`.
+*/
+
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::mem;
+use std::ops::Deref;
+use std::os::raw::c_char;
+use std::ptr;
+
+#[cfg(feature = "exception")]
 use objc_exception;
 
-use id::StrongPtr;
-use runtime::Object;
+use message::send_message;
+use runtime::{Class, Object, Sel, self};
+
+/// Returns an `NSString` with the given contents, or null if the `NSString`
+/// class isn't available.
+unsafe fn ns_string(s: &str) -> *mut Object {
+    let s = CString::new(s.replace('\0', "")).unwrap();
+    match Class::get("NSString") {
+        Some(cls) => msg_send![cls, stringWithUTF8String:s.as_ptr()],
+        None => ptr::null_mut(),
+    }
+}
+
+/// A retained Objective-C object that was or can be thrown as an exception.
+pub struct Exception {
+    ptr: *mut Object,
+}
+
+impl Exception {
+    /// Constructs an `Exception` from a pointer to an object that the caller
+    /// owns a reference to, which is released when the `Exception` is dropped.
+    /// Returns `None` if the pointer is null.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must be a valid, retained Objective-C object.
+    pub unsafe fn from_retained(ptr: *mut Object) -> Option<Exception> {
+        if ptr.is_null() { None } else { Some(Exception { ptr }) }
+    }
+
+    /// Constructs an `Exception` from a pointer to an object, retaining it.
+    /// Returns `None` if the pointer is null.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must be a valid Objective-C object.
+    pub unsafe fn retain(ptr: *mut Object) -> Option<Exception> {
+        if ptr.is_null() {
+            None
+        } else {
+            let ptr: *mut Object = msg_send![ptr, retain];
+            Exception::from_retained(ptr)
+        }
+    }
+
+    /// Creates an `NSException` with the given name and reason.
+    /// Returns `None` if the `NSException` class isn't available.
+    pub fn new(name: &str, reason: &str) -> Option<Exception> {
+        let cls = Class::get("NSException")?;
+        unsafe {
+            let name = ns_string(name);
+            let reason = ns_string(reason);
+            let user_info = ptr::null_mut::<Object>();
+            let ptr: *mut Object = msg_send![cls,
+                exceptionWithName:name reason:reason userInfo:user_info];
+            Exception::retain(ptr)
+        }
+    }
+
+    /// Returns a pointer to the exception object.
+    pub fn as_ptr(&self) -> *mut Object {
+        self.ptr
+    }
+
+    fn responds_to(&self, sel: Sel) -> bool {
+        self.class().instance_method(sel).is_some()
+    }
+
+    /// Sends the given message returning an `NSString` to self and converts
+    /// the result, if self responds to it.
+    fn string_property(&self, sel: Sel) -> Option<String> {
+        if !self.responds_to(sel) {
+            return None;
+        }
+        unsafe {
+            let string: *mut Object = send_message(self.ptr, sel, ()).ok()?;
+            if string.is_null() {
+                return None;
+            }
+            let utf8: *const c_char = msg_send![string, UTF8String];
+            if utf8.is_null() {
+                None
+            } else {
+                Some(CStr::from_ptr(utf8).to_string_lossy().into_owned())
+            }
+        }
+    }
+
+    /// Returns the name of self, if it responds to `name`.
+    pub fn name(&self) -> Option<String> {
+        self.string_property(sel!(name))
+    }
+
+    /// Returns the reason of self, if it responds to `reason`.
+    pub fn reason(&self) -> Option<String> {
+        self.string_property(sel!(reason))
+    }
+
+    /// Returns the user info dictionary of self, if it responds to
+    /// `userInfo` and has one.
+    pub fn user_info(&self) -> Option<&Object> {
+        if !self.responds_to(sel!(userInfo)) {
+            return None;
+        }
+        unsafe {
+            let user_info: *mut Object = msg_send![self.ptr, userInfo];
+            user_info.as_ref()
+        }
+    }
+}
 
-pub unsafe fn try<F, R>(closure: F) -> Result<R, Option<StrongPtr>>
+impl Deref for Exception {
+    type Target = Object;
+
+    fn deref(&self) -> &Object {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl Drop for Exception {
+    fn drop(&mut self) {
+        let _: () = unsafe { msg_send![self.ptr, release] };
+    }
+}
+
+impl fmt::Debug for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.name(), self.reason()) {
+            (Some(name), Some(reason)) => write!(f, "{:?} {}: {}", **self, name, reason),
+            (Some(name), None) => write!(f, "{:?} {}", **self, name),
+            _ => fmt::Debug::fmt(&**self, f),
+        }
+    }
+}
+
+/**
+Throws the given exception, unwinding to the nearest Objective-C `@catch`.
+
+# Safety
+
+The exception unwinds through the calling frames, so all of them must be able
+to unwind: Rust functions between the throw and the catch must be declared
+`extern "C-unwind"` if they're called from foreign code, and the exception
+must be caught before it reaches a frame that doesn't expect to unwind.
+*/
+pub unsafe fn throw(exception: Exception) -> ! {
+    // Hand our reference to the current autorelease pool, like exceptions
+    // raised from Objective-C
+    let ptr = exception.ptr;
+    mem::forget(exception);
+    let ptr: *mut Object = msg_send![ptr, autorelease];
+    runtime::objc_exception_throw(ptr)
+}
+
+/**
+Calls the given closure, catching any Objective-C exception it throws.
+
+Returns `Err(None)` if `nil` was thrown.
+
+# Safety
+
+The exception unwinds through the closure, so it must not hold any state that
+is left invalid if it doesn't return.
+*/
+#[cfg(feature = "exception")]
+pub unsafe fn catch_exception<F, R>(closure: F) -> Result<R, Option<Exception>>
         where F: FnOnce() -> R {
     objc_exception::try(closure).map_err(|exception| {
-        if exception.is_null() { None }
-        else { Some(StrongPtr::new(exception as *mut Object)) }
+        // The caught exception is retained by objc_exception
+        Exception::from_retained(exception as *mut Object)
     })
 }
+
+#[cfg(all(test, feature = "exception"))]
+mod tests {
+    use super::{Exception, catch_exception, throw};
+
+    #[test]
+    fn test_throw_catch() {
+        let exception = match Exception::new("TestException", "Testing") {
+            Some(exception) => exception,
+            // NSException requires Foundation
+            None => return,
+        };
+        let ptr = exception.as_ptr();
+        let result = unsafe {
+            catch_exception(|| -> u32 { throw(exception) })
+        };
+        let caught = result.unwrap_err().unwrap();
+        assert!(caught.as_ptr() == ptr);
+        assert!(caught.name().as_deref() == Some("TestException"));
+        assert!(caught.reason().as_deref() == Some("Testing"));
+        assert!(caught.user_info().is_none());
+    }
+}
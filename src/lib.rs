@@ -7,10 +7,10 @@ Objective-C objects can be messaged using the [`msg_send!`](macro.msg_send!.html
 
 ``` no_run
 # #[macro_use] extern crate objc;
-# use objc::runtime::{BOOL, Class, Object};
+# use objc::runtime::{BOOL, Object};
 # fn main() {
 # unsafe {
-let cls = Class::get("NSObject").unwrap();
+let cls = class!(NSObject);
 let obj: *mut Object = msg_send![cls, new];
 let hash: usize = msg_send![obj, hash];
 let is_kind: BOOL = msg_send![obj, isKindOfClass:cls];
@@ -20,10 +20,24 @@ let _: () = msg_send![obj, release];
 # }
 ```
 
+Classes are looked up with the [`class!`](macro.class!.html) macro, which
+caches the class for each call site.
+
+Like in Objective-C, messages to nil return a zeroed value by default. The
+process-wide [`NilPolicy`](enum.NilPolicy.html) can instead make them return
+an error or panic in debug builds, and can be overridden for a single message
+with `msg_send![nil = NilPolicy::Error; obj, hash]`.
+
 # Declaring classes
 
 Objective-C classes can even be declared from Rust using the functionality of
-the [`declare`](declare/index.html) module.
+the [`declare`](declare/index.html) module, or declaratively with the
+[`declare_class!`](macro.declare_class!.html) macro.
+
+# Blocks
+
+Objective-C blocks can be called, created from Rust closures, and declared as
+statics using the functionality of the [`block`](block/index.html) module.
 
 # Exceptions
 
@@ -33,6 +47,10 @@ However, this crate has an `"exception"` feature which, when enabled, wraps
 each `msg_send!` in a `@try`/`@catch` and panics if an exception is caught,
 preventing Objective-C from unwinding into Rust.
 
+Exceptions can be raised from Rust and inspected using the functionality of
+the [`exception`](exception/index.html) module. With the `"exception"` feature enabled, it can also
+catch exceptions around arbitrary code.
+
 # Message type verification
 
 The Objective-C runtime includes encodings for each method that describe the
@@ -42,13 +60,20 @@ verify that the types used in Rust match the types encoded for the method.
 To use this functionality, enable the `"verify_message"` feature.
 With this feature enabled, type checking is performed for every message send,
 which also requires that all arguments and return values for all messages
-implement `Encode`.
+implement `Encode`. Successful verifications are cached per class, selector and
+signature, so repeated sends of the same message are only checked once.
 
 If this requirement is burdensome or you'd rather
-just verify specific messages, you can call the
+just verify specific messages, you can send them with the
+[`msg_send_verified!`](macro.msg_send_verified!.html) macro instead, or call the
 [`Message::verify_message`](trait.Message.html#method.verify_message) method
 for specific selectors.
 
+Whether verified sends are actually checked is controlled at runtime by the
+process-wide [`VerificationMode`](enum.VerificationMode.html): verification can
+be turned off, restricted to builds with `debug_assertions`, always enforced
+(the default), or performed with mismatches only logged.
+
 # Support for other Operating Systems
 
 The bindings can be used on Linux or *BSD utilizing the
@@ -65,21 +90,28 @@ extern crate malloc_buf;
 extern crate objc_exception;
 
 pub use encode::{Encode, EncodeArguments, Encoding};
-pub use message::{Message, MessageArguments, MessageError};
+pub use message::{CachedMethod, Message, MessageArguments, MessageError};
+pub use message::{NilPolicy, VerificationMode};
 
 pub use message::send_message as __send_message;
 pub use message::send_super_message as __send_super_message;
+pub use message::send_message_verified as __send_message_verified;
+pub use message::send_super_message_verified as __send_super_message_verified;
+pub use message::send_message_with_nil_policy as __send_message_with_nil_policy;
+pub use message::send_super_message_with_nil_policy as __send_super_message_with_nil_policy;
+pub use message::send_message_verified_with_nil_policy as __send_message_verified_with_nil_policy;
+pub use message::send_super_message_verified_with_nil_policy as __send_super_message_verified_with_nil_policy;
+pub use cache::CachedClass as __CachedClass;
 
 #[macro_use]
 mod macros;
 
 pub mod runtime;
+pub mod block;
 pub mod declare;
+mod cache;
 mod encode;
-#[cfg(feature = "exception")]
-mod exception;
-#[cfg(feature = "exception")]
-mod id;
+pub mod exception;
 mod message;
 
 #[cfg(test)]
@@ -32,6 +32,62 @@ macro_rules! sel {
     });
 }
 
+/**
+Gets a reference to a `Class`.
+
+Panics if no class with the given name can be found.
+To check for a class that may not exist, use `try_class!`.
+
+The class is looked up once per call site and then cached, so unlike
+`Class::get` this neither allocates nor queries the runtime again on
+subsequent calls.
+
+# Example
+``` no_run
+# #[macro_use] extern crate objc;
+# fn main() {
+let cls = class!(NSObject);
+# }
+```
+*/
+#[macro_export]
+macro_rules! class {
+    ($name:ident) => ({
+        match try_class!($name) {
+            Some(cls) => cls,
+            None => panic!("Class with name {} could not be found", stringify!($name)),
+        }
+    })
+}
+
+/**
+Gets a reference to a `Class`, or `None` if no class with the given name
+is registered.
+
+Like `class!`, the class is cached per call site once it has been found.
+
+# Example
+``` no_run
+# #[macro_use] extern crate objc;
+# fn main() {
+if let Some(cls) = try_class!(NSWindow) {
+    println!("AppKit is loaded: {:?}", cls);
+}
+# }
+```
+*/
+#[macro_export]
+macro_rules! try_class {
+    ($name:ident) => ({
+        #[inline(always)]
+        fn get_class(name_with_nul: &str) -> Option<&'static $crate::runtime::Class> {
+            static CLASS: $crate::__CachedClass = $crate::__CachedClass::new();
+            unsafe { CLASS.get(name_with_nul) }
+        }
+        get_class(concat!(stringify!($name), '\0'))
+    })
+}
+
 /**
 Sends a message to an object.
 
@@ -40,9 +96,14 @@ The first argument can be any type that dereferences to a type that implements
 The syntax is similar to the message syntax in Objective-C.
 Variadic arguments are not currently supported.
 
+Messages to nil are handled according to the current `NilPolicy`, which can
+be overridden for a single message by prefixing it with `nil = policy;`.
+If the policy is `NilPolicy::Error`, messaging nil panics.
+
 # Example
 ``` no_run
 # #[macro_use] extern crate objc;
+# use objc::NilPolicy;
 # use objc::runtime::Object;
 # fn main() {
 # unsafe {
@@ -50,12 +111,41 @@ let obj: *mut Object;
 # let obj: *mut Object = 0 as *mut Object;
 let description: *const Object = msg_send![obj, description];
 let _: () = msg_send![obj, setArg1:1 arg2:2];
+let hash: usize = msg_send![nil = NilPolicy::DebugPanic; obj, hash];
 # }
 # }
 ```
 */
 #[macro_export]
 macro_rules! msg_send {
+    (nil = $nil:expr; super($obj:expr, $superclass:expr), $name:ident) => ({
+        let sel = sel!($name);
+        match $crate::__send_super_message_with_nil_policy(&*$obj, $superclass, sel, (), $nil) {
+            Err(s) => panic!("{}", s),
+            Ok(r) => r,
+        }
+    });
+    (nil = $nil:expr; super($obj:expr, $superclass:expr), $($name:ident : $arg:expr)+) => ({
+        let sel = sel!($($name:)+);
+        match $crate::__send_super_message_with_nil_policy(&*$obj, $superclass, sel, ($($arg,)*), $nil) {
+            Err(s) => panic!("{}", s),
+            Ok(r) => r,
+        }
+    });
+    (nil = $nil:expr; $obj:expr, $name:ident) => ({
+        let sel = sel!($name);
+        match $crate::__send_message_with_nil_policy(&*$obj, sel, (), $nil) {
+            Err(s) => panic!("{}", s),
+            Ok(r) => r,
+        }
+    });
+    (nil = $nil:expr; $obj:expr, $($name:ident : $arg:expr)+) => ({
+        let sel = sel!($($name:)+);
+        match $crate::__send_message_with_nil_policy(&*$obj, sel, ($($arg,)*), $nil) {
+            Err(s) => panic!("{}", s),
+            Ok(r) => r,
+        }
+    });
     (super($obj:expr, $superclass:expr), $name:ident) => ({
         let sel = sel!($name);
         match $crate::__send_super_message(&*$obj, $superclass, sel, ()) {
@@ -85,3 +175,314 @@ macro_rules! msg_send {
         }
     });
 }
+
+/**
+Sends a message to an object, verifying that the argument and return types
+match the encoding of the method.
+
+The syntax is the same as for `msg_send!`, but all arguments and the return
+type must implement `Encode`. Verification is performed according to the
+current `VerificationMode`; if it fails, this macro panics without sending
+the message (unless the mode is `VerificationMode::Warn`).
+
+This allows opting in to verification for specific call sites without
+enabling the `"verify_message"` feature for every message.
+
+# Example
+``` no_run
+# #[macro_use] extern crate objc;
+# use objc::runtime::Object;
+# fn main() {
+# unsafe {
+let obj: *mut Object;
+# let obj: *mut Object = 0 as *mut Object;
+let hash: usize = msg_send_verified![obj, hash];
+# }
+# }
+```
+*/
+#[macro_export]
+macro_rules! msg_send_verified {
+    (nil = $nil:expr; super($obj:expr, $superclass:expr), $name:ident) => ({
+        let sel = sel!($name);
+        match $crate::__send_super_message_verified_with_nil_policy(&*$obj, $superclass, sel, (), $nil) {
+            Err(s) => panic!("{}", s),
+            Ok(r) => r,
+        }
+    });
+    (nil = $nil:expr; super($obj:expr, $superclass:expr), $($name:ident : $arg:expr)+) => ({
+        let sel = sel!($($name:)+);
+        match $crate::__send_super_message_verified_with_nil_policy(&*$obj, $superclass, sel, ($($arg,)*), $nil) {
+            Err(s) => panic!("{}", s),
+            Ok(r) => r,
+        }
+    });
+    (nil = $nil:expr; $obj:expr, $name:ident) => ({
+        let sel = sel!($name);
+        match $crate::__send_message_verified_with_nil_policy(&*$obj, sel, (), $nil) {
+            Err(s) => panic!("{}", s),
+            Ok(r) => r,
+        }
+    });
+    (nil = $nil:expr; $obj:expr, $($name:ident : $arg:expr)+) => ({
+        let sel = sel!($($name:)+);
+        match $crate::__send_message_verified_with_nil_policy(&*$obj, sel, ($($arg,)*), $nil) {
+            Err(s) => panic!("{}", s),
+            Ok(r) => r,
+        }
+    });
+    (super($obj:expr, $superclass:expr), $name:ident) => ({
+        let sel = sel!($name);
+        match $crate::__send_super_message_verified(&*$obj, $superclass, sel, ()) {
+            Err(s) => panic!("{}", s),
+            Ok(r) => r,
+        }
+    });
+    (super($obj:expr, $superclass:expr), $($name:ident : $arg:expr)+) => ({
+        let sel = sel!($($name:)+);
+        match $crate::__send_super_message_verified(&*$obj, $superclass, sel, ($($arg,)*)) {
+            Err(s) => panic!("{}", s),
+            Ok(r) => r,
+        }
+    });
+    ($obj:expr, $name:ident) => ({
+        let sel = sel!($name);
+        match $crate::__send_message_verified(&*$obj, sel, ()) {
+            Err(s) => panic!("{}", s),
+            Ok(r) => r,
+        }
+    });
+    ($obj:expr, $($name:ident : $arg:expr)+) => ({
+        let sel = sel!($($name:)+);
+        match $crate::__send_message_verified(&*$obj, sel, ($($arg,)*)) {
+            Err(s) => panic!("{}", s),
+            Ok(r) => r,
+        }
+    });
+}
+
+/**
+Declares an Objective-C class along with a Rust type for its instances.
+
+The class is declared with the given superclass, adopted protocols and ivars,
+and the methods in the `impl` block are added to it, each with the selector
+given by its `#[sel(...)]` attribute. Methods taking `&self` or `&mut self`
+become instance methods and methods without a receiver become class methods.
+They're also available as inherent methods of the Rust type.
+
+The class is declared and registered the first time the `class` function
+of the Rust type is called, which panics if it couldn't be declared. Instances
+can be used as their `Object` through `Deref`, and references to them
+implement `Encode`.
+
+Panics in the methods abort the process instead of unwinding into
+Objective-C.
+
+The `impl` block must be for the declared type; naming another type is an
+error:
+
+``` compile_fail
+# #[macro_use] extern crate objc;
+struct Other;
+
+declare_class! {
+    struct MyObject: NSObject { }
+
+    impl Other { }
+}
+# fn main() { }
+```
+
+# Example
+``` no_run
+# #[macro_use] extern crate objc;
+declare_class! {
+    pub struct MyNumber: NSObject <NSCopying> {
+        _number: u32,
+    }
+
+    impl MyNumber {
+        #[sel(number)]
+        pub fn number(&self) -> u32 {
+            unsafe { *self.get_ivar("_number") }
+        }
+
+        #[sel(setNumber:)]
+        pub fn set_number(&mut self, number: u32) {
+            unsafe { self.set_ivar("_number", number); }
+        }
+
+        #[sel(defaultNumber)]
+        fn default_number() -> u32 {
+            7
+        }
+    }
+}
+
+# fn main() {
+let obj: &mut MyNumber = unsafe { msg_send![MyNumber::class(), new] };
+obj.set_number(MyNumber::default_number());
+let number: u32 = unsafe { msg_send![obj, number] };
+assert!(number == 7);
+# }
+```
+*/
+#[macro_export]
+macro_rules! declare_class {
+    (@methods $name:ident,) => ();
+    (@methods $name:ident,
+        #[sel($($sel:tt)+)] $(#[$attr:meta])*
+        $v:vis fn $m:ident(&mut $this:ident $(, $a:ident : $t:ty)* $(,)?) $(-> $r:ty)? $body:block
+        $($rest:tt)*
+    ) => (
+        impl $name {
+            $(#[$attr])*
+            $v fn $m(&mut $this $(, $a: $t)*) $(-> $r)? $body
+        }
+        declare_class!(@methods $name, $($rest)*);
+    );
+    (@methods $name:ident,
+        #[sel($($sel:tt)+)] $(#[$attr:meta])*
+        $v:vis fn $m:ident(&$this:ident $(, $a:ident : $t:ty)* $(,)?) $(-> $r:ty)? $body:block
+        $($rest:tt)*
+    ) => (
+        impl $name {
+            $(#[$attr])*
+            $v fn $m(&$this $(, $a: $t)*) $(-> $r)? $body
+        }
+        declare_class!(@methods $name, $($rest)*);
+    );
+    (@methods $name:ident,
+        #[sel($($sel:tt)+)] $(#[$attr:meta])*
+        $v:vis fn $m:ident($($a:ident : $t:ty),* $(,)?) $(-> $r:ty)? $body:block
+        $($rest:tt)*
+    ) => (
+        impl $name {
+            $(#[$attr])*
+            $v fn $m($($a: $t),*) $(-> $r)? $body
+        }
+        declare_class!(@methods $name, $($rest)*);
+    );
+
+    (@register $decl:ident, $name:ident,) => ();
+    (@register $decl:ident, $name:ident,
+        #[sel($($sel:tt)+)] $(#[$attr:meta])*
+        $v:vis fn $m:ident(&mut $this:ident $(, $a:ident : $t:ty)* $(,)?) $(-> $r:ty)? $body:block
+        $($rest:tt)*
+    ) => ({
+        extern "C" fn imp(this: &mut $crate::runtime::Object,
+                _cmd: $crate::runtime::Sel $(, $a: $t)*) $(-> $r)? {
+            let this = unsafe {
+                &mut *(this as *mut $crate::runtime::Object as *mut $name)
+            };
+            this.$m($($a),*)
+        }
+        let imp: extern "C" fn(&mut $crate::runtime::Object,
+            $crate::runtime::Sel $(, $t)*) $(-> $r)? = imp;
+        unsafe { $decl.add_method(sel!($($sel)+), imp); }
+        declare_class!(@register $decl, $name, $($rest)*);
+    });
+    (@register $decl:ident, $name:ident,
+        #[sel($($sel:tt)+)] $(#[$attr:meta])*
+        $v:vis fn $m:ident(&$this:ident $(, $a:ident : $t:ty)* $(,)?) $(-> $r:ty)? $body:block
+        $($rest:tt)*
+    ) => ({
+        extern "C" fn imp(this: &$crate::runtime::Object,
+                _cmd: $crate::runtime::Sel $(, $a: $t)*) $(-> $r)? {
+            let this = unsafe {
+                &*(this as *const $crate::runtime::Object as *const $name)
+            };
+            this.$m($($a),*)
+        }
+        let imp: extern "C" fn(&$crate::runtime::Object,
+            $crate::runtime::Sel $(, $t)*) $(-> $r)? = imp;
+        unsafe { $decl.add_method(sel!($($sel)+), imp); }
+        declare_class!(@register $decl, $name, $($rest)*);
+    });
+    (@register $decl:ident, $name:ident,
+        #[sel($($sel:tt)+)] $(#[$attr:meta])*
+        $v:vis fn $m:ident($($a:ident : $t:ty),* $(,)?) $(-> $r:ty)? $body:block
+        $($rest:tt)*
+    ) => ({
+        extern "C" fn imp(_cls: &$crate::runtime::Class,
+                _cmd: $crate::runtime::Sel $(, $a: $t)*) $(-> $r)? {
+            $name::$m($($a),*)
+        }
+        let imp: extern "C" fn(&$crate::runtime::Class,
+            $crate::runtime::Sel $(, $t)*) $(-> $r)? = imp;
+        unsafe { $decl.add_class_method(sel!($($sel)+), imp); }
+        declare_class!(@register $decl, $name, $($rest)*);
+    });
+
+    (
+        $(#[$m:meta])*
+        $v:vis struct $name:ident: $superclass:ident $(<$($proto:ident),+ $(,)?>)? {
+            $($ivar:ident: $ivar_ty:ty),* $(,)?
+        }
+
+        impl $impl_name:ident {
+            $($methods:tt)*
+        }
+    ) => (
+        $(#[$m])*
+        #[repr(C)]
+        $v struct $name {
+            _object: $crate::runtime::Object,
+        }
+
+        unsafe impl $crate::Message for $name { }
+
+        unsafe impl<'a> $crate::Encode for &'a $name {
+            fn encode() -> $crate::Encoding {
+                <&$crate::runtime::Object as $crate::Encode>::encode()
+            }
+        }
+
+        unsafe impl<'a> $crate::Encode for &'a mut $name {
+            fn encode() -> $crate::Encoding {
+                <&$crate::runtime::Object as $crate::Encode>::encode()
+            }
+        }
+
+        impl ::std::ops::Deref for $name {
+            type Target = $crate::runtime::Object;
+
+            fn deref(&self) -> &$crate::runtime::Object {
+                &self._object
+            }
+        }
+
+        impl ::std::ops::DerefMut for $name {
+            fn deref_mut(&mut self) -> &mut $crate::runtime::Object {
+                &mut self._object
+            }
+        }
+
+        impl $name {
+            /// Returns the class, declaring and registering it on first use.
+            $v fn class() -> &'static $crate::runtime::Class {
+                static CLASS: ::std::sync::OnceLock<&'static $crate::runtime::Class> =
+                    ::std::sync::OnceLock::new();
+                CLASS.get_or_init(|| {
+                    let superclass = class!($superclass);
+                    let mut decl = $crate::declare::ClassDecl::try_new(
+                        stringify!($name), superclass,
+                    ).unwrap_or_else(|e| panic!("{}", e));
+                    $($(
+                        let proto = $crate::runtime::Protocol::get(stringify!($proto))
+                            .expect(concat!("Protocol ", stringify!($proto), " not found"));
+                        decl.add_protocol(proto);
+                    )+)*
+                    $(decl.add_ivar::<$ivar_ty>(stringify!($ivar));)*
+                    declare_class!(@register decl, $name, $($methods)*);
+                    decl.register()
+                })
+            }
+        }
+
+        // The impl block must be for the declared type
+        const _: fn($name) -> $impl_name = |this| this;
+
+        declare_class!(@methods $name, $($methods)*);
+    );
+}
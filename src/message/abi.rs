@@ -0,0 +1,600 @@
+//! Classification of return types for choosing the variant of `objc_msgSend`.
+//!
+//! The Apple runtime has separate entry points for methods returning
+//! structures in memory (`_stret`) and values on the x87 floating point
+//! stack (`_fpret` and `_fp2ret`). Which one is required depends on the
+//! target's calling convention and on the layout of the return type, which
+//! is derived here from the type's `Encoding`. The classification is pure
+//! Rust and independent of the target being compiled for, so that the rules
+//! for every target can be tested on any platform.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{OnceLock, RwLock};
+
+use Encode;
+
+/// A target with its own calling convention for `objc_msgSend`.
+#[cfg_attr(not(any(target_os = "macos", target_os = "ios")), allow(dead_code))]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Target {
+    X86,
+    X86_64,
+    Arm,
+    Arm64,
+}
+
+/// The target being compiled for, or `None` if the runtime doesn't have
+/// variants of `objc_msgSend` (as is the case for GNUstep).
+#[cfg(all(any(target_os = "macos", target_os = "ios"), target_arch = "x86"))]
+const CURRENT_TARGET: Option<Target> = Some(Target::X86);
+#[cfg(all(any(target_os = "macos", target_os = "ios"), target_arch = "x86_64"))]
+const CURRENT_TARGET: Option<Target> = Some(Target::X86_64);
+#[cfg(all(any(target_os = "macos", target_os = "ios"), target_arch = "arm"))]
+const CURRENT_TARGET: Option<Target> = Some(Target::Arm);
+#[cfg(all(any(target_os = "macos", target_os = "ios"), target_arch = "aarch64"))]
+const CURRENT_TARGET: Option<Target> = Some(Target::Arm64);
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+const CURRENT_TARGET: Option<Target> = None;
+
+/// The calling convention of the target architecture, regardless of the
+/// runtime. Blocks need to know whether they return structures in memory
+/// even on runtimes without variants of `objc_msgSend`.
+#[cfg(target_arch = "x86")]
+const ARCH_TARGET: Option<Target> = Some(Target::X86);
+#[cfg(target_arch = "x86_64")]
+const ARCH_TARGET: Option<Target> = Some(Target::X86_64);
+#[cfg(target_arch = "arm")]
+const ARCH_TARGET: Option<Target> = Some(Target::Arm);
+#[cfg(target_arch = "aarch64")]
+const ARCH_TARGET: Option<Target> = Some(Target::Arm64);
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64",
+              target_arch = "arm", target_arch = "aarch64")))]
+const ARCH_TARGET: Option<Target> = None;
+
+/// The variant of `objc_msgSend` that must be used for a return type.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MsgSendKind {
+    /// The value is returned in registers, use `objc_msgSend`.
+    Normal,
+    /// The value is returned in memory provided by the caller,
+    /// use `objc_msgSend_stret`.
+    Stret,
+    /// The value is returned on the x87 floating point stack,
+    /// use `objc_msgSend_fpret`.
+    Fpret,
+    /// The value is returned as two values on the x87 floating point stack,
+    /// use `objc_msgSend_fp2ret`.
+    Fp2ret,
+}
+
+/// The layout-relevant structure of a type, parsed from its encoding.
+#[derive(Clone, Debug, PartialEq)]
+enum Type {
+    Void,
+    /// An integer (or `BOOL` or `char`) of the given size.
+    Int(usize),
+    /// A `float` or `double` of the given size.
+    Float(usize),
+    LongDouble,
+    /// Any pointer, including objects, classes, selectors and blocks.
+    Pointer,
+    Complex(Box<Type>),
+    Array(usize, Box<Type>),
+    Struct(Vec<Type>),
+    Union(Vec<Type>),
+    /// A type without an encoding, treated as a structure of the given
+    /// number of bytes.
+    Opaque(usize),
+}
+
+const QUALIFIERS: &[char] = &['r', 'n', 'N', 'o', 'O', 'R', 'V', 'A'];
+
+fn split_number(code: &str) -> (Option<usize>, &str) {
+    let end = code.find(|c: char| !c.is_ascii_digit()).unwrap_or(code.len());
+    (code[..end].parse().ok(), &code[end..])
+}
+
+/// Parses a sequence of types terminated by `end`, skipping quoted field
+/// names. Returns the types and the rest of the code after `end`.
+fn parse_members(mut code: &str, end: char) -> Option<(Vec<Type>, &str)> {
+    let mut members = Vec::new();
+    loop {
+        if code.starts_with('"') {
+            let close = code[1..].find('"')?;
+            code = &code[close + 2..];
+        }
+        if let Some(rest) = code.strip_prefix(end) {
+            return Some((members, rest));
+        }
+        let (member, rest) = parse(code)?;
+        members.push(member);
+        code = rest;
+    }
+}
+
+/// Skips the type pointed to by a pointer. Structures and unions are skipped
+/// without parsing since pointers to them are often opaque.
+fn skip_pointee(code: &str) -> Option<&str> {
+    let code = code.trim_start_matches(QUALIFIERS);
+    if !code.starts_with(['{', '(']) {
+        return parse(code).map(|(_, rest)| rest);
+    }
+    let mut depth = 0;
+    for (i, c) in code.char_indices() {
+        match c {
+            '{' | '(' => depth += 1,
+            '}' | ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&code[i + 1..]);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Parses the first type in `code`, returning it and the rest of the code.
+/// Returns `None` for malformed encodings and for bitfields, whose layout
+/// cannot be determined from the encoding alone.
+fn parse(code: &str) -> Option<(Type, &str)> {
+    let code = code.trim_start_matches(QUALIFIERS);
+    let mut chars = code.chars();
+    let ty = match chars.next()? {
+        'v' => Type::Void,
+        'c' | 'C' | 'B' => Type::Int(1),
+        's' | 'S' => Type::Int(2),
+        // In encodings, `l` and `L` are always 32 bits
+        'i' | 'I' | 'l' | 'L' => Type::Int(4),
+        'q' | 'Q' => Type::Int(8),
+        'f' => Type::Float(4),
+        'd' => Type::Float(8),
+        'D' => Type::LongDouble,
+        '*' | '#' | ':' => Type::Pointer,
+        '@' => {
+            let rest = chars.as_str();
+            // Blocks are encoded as `@?`, and objects may include their class
+            let rest = if let Some(rest) = rest.strip_prefix('?') {
+                rest
+            } else if let Some(quoted) = rest.strip_prefix('"') {
+                &quoted[quoted.find('"')? + 1..]
+            } else {
+                rest
+            };
+            return Some((Type::Pointer, rest));
+        }
+        '^' => {
+            let rest = chars.as_str();
+            // The pointee may be unknown, like for function pointers
+            let rest = match rest.strip_prefix('?') {
+                Some(rest) => rest,
+                None => skip_pointee(rest)?,
+            };
+            return Some((Type::Pointer, rest));
+        }
+        'j' => {
+            let (inner, rest) = parse(chars.as_str())?;
+            return Some((Type::Complex(Box::new(inner)), rest));
+        }
+        '[' => {
+            let (len, rest) = split_number(chars.as_str());
+            let (item, rest) = parse(rest)?;
+            let rest = rest.strip_prefix(']')?;
+            return Some((Type::Array(len?, Box::new(item)), rest));
+        }
+        open @ '{' | open @ '(' => {
+            let close = if open == '{' { '}' } else { ')' };
+            let rest = chars.as_str();
+            let name_end = rest.find(['=', close])?;
+            // Without members the layout of the type is unknown
+            let rest = rest[name_end..].strip_prefix('=')?;
+            let (members, rest) = parse_members(rest, close)?;
+            let ty = if open == '{' {
+                Type::Struct(members)
+            } else {
+                Type::Union(members)
+            };
+            return Some((ty, rest));
+        }
+        _ => return None,
+    };
+    Some((ty, chars.as_str()))
+}
+
+fn parse_complete(code: &str) -> Option<Type> {
+    match parse(code) {
+        Some((ty, "")) => Some(ty),
+        _ => None,
+    }
+}
+
+impl Target {
+    fn pointer_size(self) -> usize {
+        match self {
+            Target::X86 | Target::Arm => 4,
+            Target::X86_64 | Target::Arm64 => 8,
+        }
+    }
+
+    /// Returns the size and alignment of `ty` on self.
+    fn layout(self, ty: &Type) -> (usize, usize) {
+        match *ty {
+            Type::Void => (0, 1),
+            // 8-byte scalars are only 4-byte aligned on the 32-bit targets
+            Type::Int(8) | Type::Float(8) if self.pointer_size() == 4 => (8, 4),
+            Type::Int(size) | Type::Float(size) => (size, size),
+            Type::LongDouble => match self {
+                Target::X86 | Target::X86_64 => (16, 16),
+                Target::Arm => (8, 4),
+                Target::Arm64 => (8, 8),
+            },
+            Type::Pointer => (self.pointer_size(), self.pointer_size()),
+            Type::Complex(ref inner) => {
+                let (size, align) = self.layout(inner);
+                (size * 2, align)
+            }
+            Type::Array(len, ref item) => {
+                let (size, align) = self.layout(item);
+                (size * len, align)
+            }
+            Type::Struct(ref members) => {
+                let mut size: usize = 0;
+                let mut max_align = 1;
+                for member in members {
+                    let (member_size, align) = self.layout(member);
+                    size = size.next_multiple_of(align) + member_size;
+                    max_align = max_align.max(align);
+                }
+                (size.next_multiple_of(max_align), max_align)
+            }
+            Type::Union(ref members) => {
+                let (size, align) = members.iter()
+                    .map(|member| self.layout(member))
+                    .fold((0, 1), |(size, align), (s, a)| (size.max(s), align.max(a)));
+                (size.next_multiple_of(align), align)
+            }
+            Type::Opaque(size) => (size, 1),
+        }
+    }
+
+    /// Returns the variant of `objc_msgSend` needed to return `ty` on self.
+    fn classify(self, ty: &Type) -> MsgSendKind {
+        match self {
+            Target::X86 => self.classify_x86(ty),
+            Target::X86_64 => self.classify_x86_64(ty),
+            Target::Arm => self.classify_arm(ty),
+            // stret is not even available in arm64.
+            // <https://twitter.com/gparker/status/378079715824660480>
+            Target::Arm64 => MsgSendKind::Normal,
+        }
+    }
+
+    fn classify_x86(self, ty: &Type) -> MsgSendKind {
+        // Floating point values are returned in ST0.
+        // Structures 1 or 2 bytes in size are placed in EAX.
+        // Structures 4 or 8 bytes in size are placed in: EAX and EDX.
+        // Structures of other sizes are placed at the address supplied by the caller.
+        // <https://developer.apple.com/library/mac/documentation/DeveloperTools/Conceptual/LowLevelABI/130-IA-32_Function_Calling_Conventions/IA32.html>
+        match *ty {
+            Type::Float(_) | Type::LongDouble => MsgSendKind::Fpret,
+            Type::Void | Type::Int(_) | Type::Pointer => MsgSendKind::Normal,
+            _ => match self.layout(ty).0 {
+                0 | 1 | 2 | 4 | 8 => MsgSendKind::Normal,
+                _ => MsgSendKind::Stret,
+            },
+        }
+    }
+
+    fn classify_x86_64(self, ty: &Type) -> MsgSendKind {
+        // If the size of an object is larger than two eightbytes, it has class MEMORY.
+        // If the type has class MEMORY, then the caller provides space for the return
+        // value and passes the address of this storage.
+        // Values of class X87 are returned in ST0, and of class COMPLEX_X87
+        // in ST0 and ST1.
+        // <http://people.freebsd.org/~obrien/amd64-elf-abi.pdf>
+        match *ty {
+            Type::LongDouble => return MsgSendKind::Fpret,
+            Type::Complex(ref inner) if **inner == Type::LongDouble => {
+                return MsgSendKind::Fp2ret;
+            }
+            Type::Void | Type::Int(_) | Type::Float(_) | Type::Pointer => {
+                return MsgSendKind::Normal;
+            }
+            _ => (),
+        }
+
+        let size = self.layout(ty).0;
+        if size > 16 {
+            return MsgSendKind::Stret;
+        }
+        let mut classes = [ArgClass::NoClass; 2];
+        if !self.classify_eightbytes(ty, 0, &mut classes) {
+            return MsgSendKind::Stret;
+        }
+        match classes {
+            [ArgClass::Memory, _] | [_, ArgClass::Memory] => MsgSendKind::Stret,
+            [ArgClass::X87, ArgClass::X87Up] => MsgSendKind::Fpret,
+            [_, ArgClass::X87Up] | [ArgClass::X87Up, _] => MsgSendKind::Stret,
+            _ => MsgSendKind::Normal,
+        }
+    }
+
+    /// Merges the classes of the scalars in `ty`, located at `offset`, into
+    /// `classes`. Returns false if `ty` contains unaligned fields, in which
+    /// case it has class MEMORY.
+    fn classify_eightbytes(self, ty: &Type, offset: usize,
+            classes: &mut [ArgClass; 2]) -> bool {
+        let (size, align) = self.layout(ty);
+        // Alignments are powers of two
+        if offset & (align - 1) != 0 {
+            return false;
+        }
+        let scalar_class = match *ty {
+            Type::Void => return true,
+            Type::Int(_) | Type::Pointer => ArgClass::Integer,
+            Type::Float(_) => ArgClass::Sse,
+            Type::LongDouble => {
+                let index = offset / 8;
+                classes[index] = classes[index].merge(ArgClass::X87);
+                classes[index + 1] = classes[index + 1].merge(ArgClass::X87Up);
+                return true;
+            }
+            Type::Complex(ref inner) | Type::Array(_, ref inner) => {
+                let item_size = self.layout(inner).0;
+                let count = size.checked_div(item_size).unwrap_or(0);
+                return (0..count).all(|i| {
+                    self.classify_eightbytes(inner, offset + i * item_size, classes)
+                });
+            }
+            Type::Struct(ref members) => {
+                let mut member_offset = offset;
+                for member in members {
+                    let (member_size, member_align) = self.layout(member);
+                    member_offset = member_offset.next_multiple_of(member_align);
+                    if !self.classify_eightbytes(member, member_offset, classes) {
+                        return false;
+                    }
+                    member_offset += member_size;
+                }
+                return true;
+            }
+            Type::Union(ref members) => {
+                return members.iter().all(|member| {
+                    self.classify_eightbytes(member, offset, classes)
+                });
+            }
+            Type::Opaque(_) => {
+                if size > 0 {
+                    for class in &mut classes[offset / 8..=(offset + size - 1) / 8] {
+                        *class = class.merge(ArgClass::Integer);
+                    }
+                }
+                return true;
+            }
+        };
+        let index = offset / 8;
+        classes[index] = classes[index].merge(scalar_class);
+        true
+    }
+
+    fn classify_arm(self, ty: &Type) -> MsgSendKind {
+        // Double-word sized fundamental data types don't use stret,
+        // but any composite type larger than 4 bytes does.
+        // <http://infocenter.arm.com/help/topic/com.arm.doc.ihi0042e/IHI0042E_aapcs.pdf>
+        match *ty {
+            Type::Void | Type::Int(_) | Type::Float(_) | Type::LongDouble |
+            Type::Pointer => MsgSendKind::Normal,
+            _ if self.layout(ty).0 <= 4 => MsgSendKind::Normal,
+            _ => MsgSendKind::Stret,
+        }
+    }
+}
+
+/// The classes of eightbytes in the x86_64 System V ABI.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ArgClass {
+    NoClass,
+    Integer,
+    Sse,
+    X87,
+    X87Up,
+    Memory,
+}
+
+impl ArgClass {
+    fn merge(self, other: ArgClass) -> ArgClass {
+        use self::ArgClass::*;
+
+        match (self, other) {
+            (a, b) if a == b => a,
+            (NoClass, x) | (x, NoClass) => x,
+            (Memory, _) | (_, Memory) => Memory,
+            (Integer, _) | (_, Integer) => Integer,
+            (X87, _) | (_, X87) | (X87Up, _) | (_, X87Up) => Memory,
+            _ => Sse,
+        }
+    }
+}
+
+/// Describes a type which has no encoding using only its size and whether
+/// it is one of the primitive types with special handling.
+fn fallback_type<R>() -> Type where R: Any {
+    let type_id = TypeId::of::<R>();
+    let size = mem::size_of::<R>();
+    if type_id == TypeId::of::<f32>() || type_id == TypeId::of::<f64>() {
+        Type::Float(size)
+    } else if type_id == TypeId::of::<i64>() || type_id == TypeId::of::<u64>() {
+        Type::Int(size)
+    } else if size == 0 {
+        Type::Void
+    } else {
+        Type::Opaque(size)
+    }
+}
+
+/// Returns the variant of `objc_msgSend` to use for the return type `R` on
+/// the current target, determined only from its size and type.
+///
+/// This cannot detect all cases that need special handling (for example,
+/// structures containing `long double`), so when `R` implements `Encode`
+/// `encoded_return_kind` should be preferred.
+#[cfg_attr(feature = "verify_message", allow(dead_code))]
+#[inline]
+pub fn return_kind<R>() -> MsgSendKind where R: Any {
+    match CURRENT_TARGET {
+        Some(target) => target.classify(&fallback_type::<R>()),
+        None => MsgSendKind::Normal,
+    }
+}
+
+/// Returns the variant of `objc_msgSend` to use for the return type `R` on
+/// the current target, determined from its encoding.
+/// The classification is cached per type, since parsing the encoding is
+/// comparatively expensive.
+#[inline]
+pub fn encoded_return_kind<R>() -> MsgSendKind where R: Any + Encode {
+    match CURRENT_TARGET {
+        // Every type is returned in registers on arm64
+        Some(Target::Arm64) | None => MsgSendKind::Normal,
+        Some(target) => cached_return_kind::<R>(target),
+    }
+}
+
+fn cached_return_kind<R>(target: Target) -> MsgSendKind where R: Any + Encode {
+    static CACHE: OnceLock<RwLock<HashMap<TypeId, MsgSendKind>>> = OnceLock::new();
+    let cache = CACHE.get_or_init(|| RwLock::new(HashMap::new()));
+    let type_id = TypeId::of::<R>();
+    let cached = cache.read().ok()
+        .and_then(|cache| cache.get(&type_id).cloned());
+    if let Some(kind) = cached {
+        return kind;
+    }
+
+    let ty = parse_complete(R::encode().as_str())
+        .unwrap_or_else(fallback_type::<R>);
+    let kind = target.classify(&ty);
+    cache.write().unwrap_or_else(|e| e.into_inner()).insert(type_id, kind);
+    kind
+}
+
+/// Returns whether the return type `R` is returned in memory provided by the
+/// caller on the current architecture, as blocks must flag with
+/// `BLOCK_USE_STRET`.
+pub fn encoded_returns_in_memory<R>() -> bool where R: Any + Encode {
+    match ARCH_TARGET {
+        Some(target) => {
+            let ty = parse_complete(R::encode().as_str())
+                .unwrap_or_else(fallback_type::<R>);
+            target.classify(&ty) == MsgSendKind::Stret
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_utils::CustomStruct;
+    use super::{MsgSendKind, Target, Type, fallback_type, parse_complete};
+    use super::MsgSendKind::*;
+    use super::Target::*;
+
+    fn classify(target: Target, code: &str) -> MsgSendKind {
+        let ty = parse_complete(code)
+            .unwrap_or_else(|| panic!("Failed to parse {}", code));
+        target.classify(&ty)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_complete("v"), Some(Type::Void));
+        assert_eq!(parse_complete("r^v"), Some(Type::Pointer));
+        assert_eq!(parse_complete("@\"NSString\""), Some(Type::Pointer));
+        assert_eq!(parse_complete("@?"), Some(Type::Pointer));
+        assert_eq!(parse_complete("^{Opaque}"), Some(Type::Pointer));
+        assert_eq!(parse_complete("^?"), Some(Type::Pointer));
+        assert_eq!(parse_complete("^{Flags=b1b7}"), Some(Type::Pointer));
+        assert_eq!(parse_complete("{List=^{List}i}"),
+            Some(Type::Struct(vec![Type::Pointer, Type::Int(4)])));
+        assert_eq!(parse_complete("[4c]"),
+            Some(Type::Array(4, Box::new(Type::Int(1)))));
+        assert_eq!(parse_complete("{Pair=\"a\"i\"b\"d}"),
+            Some(Type::Struct(vec![Type::Int(4), Type::Float(8)])));
+        assert_eq!(parse_complete("(?=qD)"),
+            Some(Type::Union(vec![Type::Int(8), Type::LongDouble])));
+        assert_eq!(parse_complete("jf"),
+            Some(Type::Complex(Box::new(Type::Float(4)))));
+
+        // Bitfields and opaque structures have no known layout
+        assert_eq!(parse_complete("{Flags=b1b7}"), None);
+        assert_eq!(parse_complete("{Opaque}"), None);
+        assert_eq!(parse_complete("{Unterminated=ii"), None);
+        assert_eq!(parse_complete("ii"), None);
+    }
+
+    #[test]
+    fn test_layout() {
+        let ty = parse_complete("{?=cdc}").unwrap();
+        assert_eq!(X86_64.layout(&ty), (24, 8));
+        assert_eq!(X86.layout(&ty), (16, 4));
+        let ty = parse_complete("{?=iD}").unwrap();
+        assert_eq!(X86_64.layout(&ty), (32, 16));
+        assert_eq!(Arm.layout(&ty), (12, 4));
+    }
+
+    #[test]
+    fn test_classify_x86() {
+        assert_eq!(classify(X86, "v"), Normal);
+        assert_eq!(classify(X86, "i"), Normal);
+        assert_eq!(classify(X86, "q"), Normal);
+        assert_eq!(classify(X86, "@"), Normal);
+        assert_eq!(classify(X86, "f"), Fpret);
+        assert_eq!(classify(X86, "d"), Fpret);
+        assert_eq!(classify(X86, "D"), Fpret);
+        assert_eq!(classify(X86, "{CGPoint=ff}"), Normal);
+        assert_eq!(classify(X86, "{?=ccc}"), Stret);
+        assert_eq!(classify(X86, "{CGRect={CGPoint=ff}{CGSize=ff}}"), Stret);
+    }
+
+    #[test]
+    fn test_classify_x86_64() {
+        assert_eq!(classify(X86_64, "d"), Normal);
+        assert_eq!(classify(X86_64, "D"), Fpret);
+        assert_eq!(classify(X86_64, "jD"), Fp2ret);
+        assert_eq!(classify(X86_64, "jd"), Normal);
+        assert_eq!(classify(X86_64, "{CGPoint=dd}"), Normal);
+        assert_eq!(classify(X86_64, "{?=[4c]Q}"), Normal);
+        assert_eq!(classify(X86_64, "{CGRect={CGPoint=dd}{CGSize=dd}}"), Stret);
+        // A lone long double is returned in ST0 like the scalar
+        assert_eq!(classify(X86_64, "{?=D}"), Fpret);
+        // Long doubles that share an eightbyte force the structure to memory
+        assert_eq!(classify(X86_64, "(?=qD)"), Stret);
+        assert_eq!(classify(X86_64, "{?=iD}"), Stret);
+    }
+
+    #[test]
+    fn test_classify_arm() {
+        assert_eq!(classify(Arm, "d"), Normal);
+        assert_eq!(classify(Arm, "q"), Normal);
+        assert_eq!(classify(Arm, "{?=cc}"), Normal);
+        assert_eq!(classify(Arm, "{?=i}"), Normal);
+        assert_eq!(classify(Arm, "{?=ii}"), Stret);
+
+        assert_eq!(classify(Arm64, "{CGRect={CGPoint=dd}{CGSize=dd}}"), Normal);
+        assert_eq!(classify(Arm64, "D"), Normal);
+    }
+
+    #[test]
+    fn test_classify_fallback() {
+        assert_eq!(X86.classify(&fallback_type::<f64>()), Fpret);
+        assert_eq!(X86.classify(&fallback_type::<[u8; 3]>()), Stret);
+        assert_eq!(X86.classify(&fallback_type::<[u8; 4]>()), Normal);
+        assert_eq!(X86_64.classify(&fallback_type::<CustomStruct>()), Stret);
+        assert_eq!(X86_64.classify(&fallback_type::<[u64; 2]>()), Normal);
+        assert_eq!(X86_64.classify(&fallback_type::<[u8; 12]>()), Normal);
+        assert_eq!(Arm.classify(&fallback_type::<u64>()), Normal);
+        assert_eq!(Arm.classify(&fallback_type::<[u32; 2]>()), Stret);
+    }
+}
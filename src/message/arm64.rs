@@ -1,7 +1,8 @@
 use runtime::{Object, Imp, Sel};
 use super::Super;
+use super::abi::MsgSendKind;
 
-pub fn msg_send_fn<R>(obj: *mut Object, _: Sel) -> (Imp, *mut Object) {
+pub fn msg_send_fn(obj: *mut Object, _: Sel, _: MsgSendKind) -> (Imp, *mut Object) {
     // stret is not even available in arm64.
     // <https://twitter.com/gparker/status/378079715824660480>
 
@@ -12,7 +13,7 @@ pub fn msg_send_fn<R>(obj: *mut Object, _: Sel) -> (Imp, *mut Object) {
     (objc_msgSend, obj)
 }
 
-pub fn msg_send_super_fn<R>(sup: &Super, _: Sel) -> (Imp, *mut Object) {
+pub fn msg_send_super_fn(sup: &Super, _: Sel, _: MsgSendKind) -> (Imp, *mut Object) {
     extern {
         fn objc_msgSendSuper();
     }
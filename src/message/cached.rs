@@ -0,0 +1,145 @@
+use std::any::Any;
+use std::fmt;
+use std::marker::PhantomData;
+
+use runtime::{self, Class, Imp, Object, Sel};
+use {Encode, EncodeArguments};
+use super::{Message, MessageArguments, MessageError, NilPolicy};
+use super::nil::nil_return;
+use super::verify::verify_message_signature;
+
+/**
+A method implementation resolved once for a class and selector, which can
+then be invoked repeatedly without looking it up again.
+
+The signature of the method is verified against the argument types `A` and
+return type `R` when the `CachedMethod` is created, so invoking it performs
+no per-call lookup or verification.
+
+# Invalidation
+
+The `Imp` is captured at creation time. It will not observe later changes
+to the method, so the cache must be refreshed (with `refresh`) or recreated
+if the method is swizzled or replaced (e.g. by
+`method_setImplementation`, `method_exchangeImplementations` or
+`class_replaceMethod`), or if a category overriding it is loaded.
+`is_stale` can be used to check whether this has happened.
+
+The receivers the method is invoked on must be instances of the class it was
+resolved for, or of subclasses that do not override the method; invoking the
+cached implementation on a subclass which overrides it will bypass the
+override.
+
+# Example
+``` no_run
+# #[macro_use] extern crate objc;
+# use objc::CachedMethod;
+# use objc::runtime::Object;
+# fn main() {
+let cls = class!(NSObject);
+let hash = CachedMethod::<(), usize>::new(cls, sel!(hash)).unwrap();
+# let objects: Vec<*mut Object> = Vec::new();
+for &obj in &objects {
+    let h = unsafe { hash.invoke(obj, ()) }.unwrap();
+    println!("{}", h);
+}
+# }
+```
+*/
+pub struct CachedMethod<A, R> {
+    cls: *const Class,
+    sel: Sel,
+    imp: Imp,
+    args: PhantomData<fn(A) -> R>,
+}
+
+impl<A, R> CachedMethod<A, R> {
+    /// Returns the class the implementation was resolved for.
+    pub fn class(&self) -> &Class {
+        unsafe { &*self.cls }
+    }
+
+    /// Returns the selector of the cached method.
+    pub fn selector(&self) -> Sel {
+        self.sel
+    }
+
+    /// Returns the cached implementation.
+    pub fn implementation(&self) -> Imp {
+        self.imp
+    }
+
+    /// Returns true if the class now resolves the selector to a different
+    /// implementation than the cached one, for example because the method
+    /// was swizzled.
+    pub fn is_stale(&self) -> bool {
+        let current = unsafe {
+            runtime::class_getMethodImplementation(self.cls, self.sel)
+        };
+        current as usize != self.imp as usize
+    }
+}
+
+impl<A, R> CachedMethod<A, R>
+        where A: MessageArguments + EncodeArguments, R: Any + Encode {
+    /// Looks up and caches the implementation of the instance method of
+    /// `cls` with the given selector.
+    /// Returns a `MessageError` if `cls` does not implement the method or if
+    /// its signature does not match the arguments `A` and return type `R`.
+    ///
+    /// To cache a class method, pass the metaclass as `cls`.
+    pub fn new(cls: &Class, sel: Sel) -> Result<CachedMethod<A, R>, MessageError> {
+        verify_message_signature::<A, R>(cls, sel)?;
+        let imp = unsafe {
+            runtime::class_getMethodImplementation(cls, sel)
+        };
+        Ok(CachedMethod { cls, sel, imp, args: PhantomData })
+    }
+
+    /// Resolves the implementation again and verifies its signature,
+    /// replacing the cached one.
+    pub fn refresh(&mut self) -> Result<(), MessageError> {
+        *self = CachedMethod::new(self.class(), self.sel)?;
+        Ok(())
+    }
+
+    /**
+    Invokes the cached implementation on `obj` with the given arguments.
+
+    Messages to nil are handled according to the current `NilPolicy`
+    without calling the implementation.
+
+    # Safety
+
+    `obj` must be an instance of the class this method was resolved for,
+    (or a subclass not overriding the method) and the arguments must satisfy
+    the method's requirements.
+    */
+    #[inline]
+    pub unsafe fn invoke<T>(&self, obj: *const T, args: A) -> Result<R, MessageError>
+            where T: Message {
+        if obj.is_null() {
+            return nil_return(self.sel, NilPolicy::current());
+        }
+        let obj = obj as *mut T as *mut Object;
+        objc_try!({
+            A::invoke(self.imp, obj, self.sel, args)
+        })
+    }
+}
+
+impl<A, R> Clone for CachedMethod<A, R> {
+    fn clone(&self) -> CachedMethod<A, R> {
+        CachedMethod { cls: self.cls, sel: self.sel, imp: self.imp, args: PhantomData }
+    }
+}
+
+impl<A, R> fmt::Debug for CachedMethod<A, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<CachedMethod -[{:?} {:?}]>", self.class(), self.sel)
+    }
+}
+
+// The class and implementation are immutable, registered runtime entities
+unsafe impl<A, R> Sync for CachedMethod<A, R> { }
+unsafe impl<A, R> Send for CachedMethod<A, R> { }
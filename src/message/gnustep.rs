@@ -1,7 +1,11 @@
 use runtime::{Object, Imp, Sel};
 use super::Super;
+use super::abi::MsgSendKind;
 
-pub fn msg_send_fn<R>(obj: *mut Object, sel: Sel) -> (Imp, *mut Object) {
+// The IMP returned by the lookup is called directly, so there are no
+// special variants for different return types.
+
+pub fn msg_send_fn(obj: *mut Object, sel: Sel, _: MsgSendKind) -> (Imp, *mut Object) {
     extern {
         fn objc_msg_lookup(receiver: *mut Object, op: Sel) -> Imp;
     }
@@ -12,7 +16,7 @@ pub fn msg_send_fn<R>(obj: *mut Object, sel: Sel) -> (Imp, *mut Object) {
     (imp_fn, obj)
 }
 
-pub fn msg_send_super_fn<R>(sup: &Super, sel: Sel) -> (Imp, *mut Object) {
+pub fn msg_send_super_fn(sup: &Super, sel: Sel, _: MsgSendKind) -> (Imp, *mut Object) {
     extern {
         fn objc_msg_lookup_super(sup: *const Super, sel: Sel) -> Imp;
     }
@@ -6,6 +6,8 @@ use std::mem;
 use runtime::{Class, Imp, Object, Sel};
 use {Encode, EncodeArguments};
 
+mod abi;
+mod nil;
 mod verify;
 
 #[cfg(all(any(target_os = "macos", target_os = "ios"),
@@ -28,8 +30,16 @@ mod platform;
 #[path = "gnustep.rs"]
 mod platform;
 
+use self::abi::{MsgSendKind, encoded_return_kind};
+#[cfg(not(feature = "verify_message"))]
+use self::abi::return_kind;
+use self::nil::nil_return;
 use self::platform::{msg_send_fn, msg_send_super_fn};
-use self::verify::verify_message_signature;
+use self::verify::{verify_for_mode, verify_message_signature};
+
+pub use self::abi::encoded_returns_in_memory;
+pub use self::nil::NilPolicy;
+pub use self::verify::{VerificationMode, invalidate_verified_methods};
 
 /// Specifies the superclass of an instance.
 #[repr(C)]
@@ -96,6 +106,27 @@ pub unsafe trait Message {
         let obj = unsafe { &*(self as *const _ as *const Object) };
         verify_message_signature::<A, R>(obj.class(), sel)
     }
+
+    /**
+    Verifies that the argument and return types match the encoding of the
+    method that a message to super with the given selector would invoke.
+
+    The method is looked up starting at `superclass`. If self is a class,
+    the method is looked up among the class methods of `superclass` (i.e. in
+    its metaclass), so passing either the superclass or its metaclass
+    verifies the method a class method sent to super will invoke. Messages
+    to super are always dispatched to the class they are given, so class
+    methods must be sent with the metaclass of the superclass.
+
+    Class methods sent directly to a class can be verified with
+    `verify_message`, since the class of a class object is its metaclass.
+    */
+    fn verify_super_message<A, R>(&self, superclass: &Class, sel: Sel)
+            -> Result<(), MessageError>
+            where Self: Sized, A: EncodeArguments, R: Encode {
+        let obj = unsafe { &*(self as *const _ as *const Object) };
+        verify_message_signature::<A, R>(super_lookup_class(obj, superclass), sel)
+    }
 }
 
 unsafe impl Message for Object { }
@@ -126,50 +157,71 @@ macro_rules! message_args_impl {
     );
 }
 
-message_args_impl!();
-message_args_impl!(a: A);
-message_args_impl!(a: A, b: B);
-message_args_impl!(a: A, b: B, c: C);
-message_args_impl!(a: A, b: B, c: C, d: D);
-message_args_impl!(a: A, b: B, c: C, d: D, e: E);
-message_args_impl!(a: A, b: B, c: C, d: D, e: E, f: F);
-message_args_impl!(a: A, b: B, c: C, d: D, e: E, f: F, g: G);
-message_args_impl!(a: A, b: B, c: C, d: D, e: E, f: F, g: G, h: H);
-message_args_impl!(a: A, b: B, c: C, d: D, e: E, f: F, g: G, h: H, i: I);
-message_args_impl!(a: A, b: B, c: C, d: D, e: E, f: F, g: G, h: H, i: I, j: J);
-message_args_impl!(a: A, b: B, c: C, d: D, e: E, f: F, g: G, h: H, i: I, j: J, k: K);
-message_args_impl!(a: A, b: B, c: C, d: D, e: E, f: F, g: G, h: H, i: I, j: J, k: K, l: L);
+// Implements the trait for a tuple of the given arguments and for each of its
+// suffixes, so every arity up to the longest list is covered by one invocation.
+macro_rules! message_args_impls {
+    () => (
+        message_args_impl!();
+    );
+    ($a:ident : $t:ident $(, $b:ident : $u:ident)*) => (
+        message_args_impl!($a : $t $(, $b : $u)*);
+        message_args_impls!($($b : $u),*);
+    );
+}
+
+message_args_impls!(a1: A1, a2: A2, a3: A3, a4: A4, a5: A5, a6: A6, a7: A7,
+    a8: A8, a9: A9, a10: A10, a11: A11, a12: A12, a13: A13, a14: A14, a15: A15,
+    a16: A16, a17: A17, a18: A18, a19: A19, a20: A20, a21: A21, a22: A22,
+    a23: A23, a24: A24, a25: A25, a26: A26, a27: A27, a28: A28, a29: A29,
+    a30: A30, a31: A31, a32: A32);
 
 /**
 An error encountered while attempting to send a message.
 
-Currently, an error may be returned in two cases:
+Currently, an error may be returned in three cases:
 
+* the receiver is nil and the `NilPolicy` is `NilPolicy::Error`
 * an Objective-C exception is thrown and the `exception` feature is enabled
 * the encodings of the arguments do not match the encoding of the method
-  and the `verify_message` feature is enabled
+  and the message was sent with `msg_send_verified!` or the `verify_message`
+  feature is enabled
 */
 #[derive(Debug)]
-pub struct MessageError(String);
+pub enum MessageError {
+    /// The message with the given selector was sent to nil.
+    NilReceiver(Sel),
+    /// The argument or return types did not match the method's encoding.
+    Verification(String),
+    /// An Objective-C exception was thrown while sending the message.
+    Exception(String),
+}
 
 impl fmt::Display for MessageError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Display::fmt(&self.0, f)
+        match *self {
+            MessageError::NilReceiver(sel) => write!(f, "Messaging {:?} to nil", sel),
+            MessageError::Verification(ref s) |
+            MessageError::Exception(ref s) => fmt::Display::fmt(s, f),
+        }
     }
 }
 
 impl Error for MessageError {
     fn description(&self) -> &str {
-        &self.0
+        match *self {
+            MessageError::NilReceiver(_) => "message sent to nil",
+            MessageError::Verification(ref s) |
+            MessageError::Exception(ref s) => s,
+        }
     }
 }
 
 #[cfg(feature = "exception")]
 macro_rules! objc_try {
     ($b:block) => (
-        $crate::exception::try(|| $b).map_err(|exception| match exception {
-            Some(exception) => MessageError(format!("Uncaught exception {:?}", &*exception)),
-            None => MessageError("Uncaught exception nil".to_owned()),
+        $crate::exception::catch_exception(|| $b).map_err(|exception| match exception {
+            Some(exception) => MessageError::Exception(format!("Uncaught exception {:?}", exception)),
+            None => MessageError::Exception("Uncaught exception nil".to_owned()),
         })
     )
 }
@@ -179,10 +231,18 @@ macro_rules! objc_try {
     ($b:block) => (Ok($b))
 }
 
-unsafe fn send_unverified<T, A, R>(obj: *const T, sel: Sel, args: A)
-        -> Result<R, MessageError>
+// Declared after objc_try so the macro is in scope
+mod cached;
+
+pub use self::cached::CachedMethod;
+
+unsafe fn send_unverified<T, A, R>(obj: *const T, sel: Sel, args: A,
+        nil: NilPolicy, kind: MsgSendKind) -> Result<R, MessageError>
         where T: Message, A: MessageArguments, R: Any {
-    let (msg_send_fn, receiver) = msg_send_fn::<R>(obj as *mut T as *mut Object, sel);
+    if obj.is_null() {
+        return nil_return(sel, nil);
+    }
+    let (msg_send_fn, receiver) = msg_send_fn(obj as *mut T as *mut Object, sel, kind);
     objc_try!({
         A::invoke(msg_send_fn, receiver, sel, args)
     })
@@ -194,7 +254,7 @@ unsafe fn send_unverified<T, A, R>(obj: *const T, sel: Sel, args: A)
 pub unsafe fn send_message<T, A, R>(obj: *const T, sel: Sel, args: A)
         -> Result<R, MessageError>
         where T: Message, A: MessageArguments, R: Any {
-    send_unverified(obj, sel, args)
+    send_message_with_nil_policy(obj, sel, args, NilPolicy::current())
 }
 
 #[doc(hidden)]
@@ -204,22 +264,69 @@ pub unsafe fn send_message<T, A, R>(obj: *const T, sel: Sel, args: A)
         -> Result<R, MessageError>
         where T: Message, A: MessageArguments + EncodeArguments,
         R: Any + Encode {
-    let cls = if obj.is_null() {
-        return Err(MessageError(format!("Messaging {:?} to nil", sel)));
-    } else {
-        (*(obj as *const Object)).class()
-    };
+    send_message_with_nil_policy(obj, sel, args, NilPolicy::current())
+}
 
-    verify_message_signature::<A, R>(cls, sel).and_then(|_| {
-        send_unverified(obj, sel, args)
-    })
+#[doc(hidden)]
+#[inline(always)]
+#[cfg(not(feature = "verify_message"))]
+pub unsafe fn send_message_with_nil_policy<T, A, R>(obj: *const T, sel: Sel,
+        args: A, nil: NilPolicy) -> Result<R, MessageError>
+        where T: Message, A: MessageArguments, R: Any {
+    send_unverified(obj, sel, args, nil, return_kind::<R>())
+}
+
+#[doc(hidden)]
+#[inline(always)]
+#[cfg(feature = "verify_message")]
+pub unsafe fn send_message_with_nil_policy<T, A, R>(obj: *const T, sel: Sel,
+        args: A, nil: NilPolicy) -> Result<R, MessageError>
+        where T: Message, A: MessageArguments + EncodeArguments,
+        R: Any + Encode {
+    send_message_verified_with_nil_policy(obj, sel, args, nil)
+}
+
+#[doc(hidden)]
+#[inline(always)]
+pub unsafe fn send_message_verified<T, A, R>(obj: *const T, sel: Sel, args: A)
+        -> Result<R, MessageError>
+        where T: Message, A: MessageArguments + EncodeArguments,
+        R: Any + Encode {
+    send_message_verified_with_nil_policy(obj, sel, args, NilPolicy::current())
+}
+
+#[doc(hidden)]
+#[inline(always)]
+pub unsafe fn send_message_verified_with_nil_policy<T, A, R>(obj: *const T,
+        sel: Sel, args: A, nil: NilPolicy) -> Result<R, MessageError>
+        where T: Message, A: MessageArguments + EncodeArguments,
+        R: Any + Encode {
+    if !obj.is_null() {
+        verify_for_mode::<A, R>((*(obj as *const Object)).class(), sel)?;
+    }
+    send_unverified(obj, sel, args, nil, encoded_return_kind::<R>())
+}
+
+/// Returns the class in which a message to super of `obj` looks up its
+/// method; if `obj` is a class, this is the metaclass of `superclass`.
+fn super_lookup_class<'a>(obj: &Object, superclass: &'a Class) -> &'a Class {
+    if obj.class().is_metaclass() && !superclass.is_metaclass() {
+        superclass.metaclass()
+    } else {
+        superclass
+    }
 }
 
 unsafe fn send_super_unverified<T, A, R>(obj: *const T, superclass: &Class,
-        sel: Sel, args: A) -> Result<R, MessageError>
+        sel: Sel, args: A, nil: NilPolicy, kind: MsgSendKind)
+        -> Result<R, MessageError>
         where T: Message, A: MessageArguments, R: Any {
-    let sup = Super { receiver: obj as *mut T as *mut Object, superclass: superclass };
-    let (msg_send_fn, receiver) = msg_send_super_fn::<R>(&sup, sel);
+    let receiver = obj as *mut T as *mut Object;
+    if receiver.is_null() {
+        return nil_return(sel, nil);
+    }
+    let sup = Super { receiver, superclass };
+    let (msg_send_fn, receiver) = msg_send_super_fn(&sup, sel, kind);
     objc_try!({
         A::invoke(msg_send_fn, receiver, sel, args)
     })
@@ -231,7 +338,8 @@ unsafe fn send_super_unverified<T, A, R>(obj: *const T, superclass: &Class,
 pub unsafe fn send_super_message<T, A, R>(obj: *const T, superclass: &Class,
         sel: Sel, args: A) -> Result<R, MessageError>
         where T: Message, A: MessageArguments, R: Any {
-    send_super_unverified(obj, superclass, sel, args)
+    send_super_message_with_nil_policy(obj, superclass, sel, args,
+        NilPolicy::current())
 }
 
 #[doc(hidden)]
@@ -241,20 +349,62 @@ pub unsafe fn send_super_message<T, A, R>(obj: *const T, superclass: &Class,
         sel: Sel, args: A) -> Result<R, MessageError>
         where T: Message, A: MessageArguments + EncodeArguments,
         R: Any + Encode {
-    if obj.is_null() {
-        return Err(MessageError(format!("Messaging {:?} to nil", sel)));
-    }
+    send_super_message_with_nil_policy(obj, superclass, sel, args,
+        NilPolicy::current())
+}
 
-    verify_message_signature::<A, R>(superclass, sel).and_then(|_| {
-        send_super_unverified(obj, superclass, sel, args)
-    })
+#[doc(hidden)]
+#[inline(always)]
+#[cfg(not(feature = "verify_message"))]
+pub unsafe fn send_super_message_with_nil_policy<T, A, R>(obj: *const T,
+        superclass: &Class, sel: Sel, args: A, nil: NilPolicy)
+        -> Result<R, MessageError>
+        where T: Message, A: MessageArguments, R: Any {
+    send_super_unverified(obj, superclass, sel, args, nil, return_kind::<R>())
+}
+
+#[doc(hidden)]
+#[inline(always)]
+#[cfg(feature = "verify_message")]
+pub unsafe fn send_super_message_with_nil_policy<T, A, R>(obj: *const T,
+        superclass: &Class, sel: Sel, args: A, nil: NilPolicy)
+        -> Result<R, MessageError>
+        where T: Message, A: MessageArguments + EncodeArguments,
+        R: Any + Encode {
+    send_super_message_verified_with_nil_policy(obj, superclass, sel, args, nil)
+}
+
+#[doc(hidden)]
+#[inline(always)]
+pub unsafe fn send_super_message_verified<T, A, R>(obj: *const T,
+        superclass: &Class, sel: Sel, args: A) -> Result<R, MessageError>
+        where T: Message, A: MessageArguments + EncodeArguments,
+        R: Any + Encode {
+    send_super_message_verified_with_nil_policy(obj, superclass, sel, args,
+        NilPolicy::current())
+}
+
+#[doc(hidden)]
+#[inline(always)]
+pub unsafe fn send_super_message_verified_with_nil_policy<T, A, R>(
+        obj: *const T, superclass: &Class, sel: Sel, args: A, nil: NilPolicy)
+        -> Result<R, MessageError>
+        where T: Message, A: MessageArguments + EncodeArguments,
+        R: Any + Encode {
+    if !obj.is_null() {
+        let lookup = super_lookup_class(&*(obj as *const Object), superclass);
+        verify_for_mode::<A, R>(lookup, sel)?;
+    }
+    send_super_unverified(obj, superclass, sel, args, nil,
+        encoded_return_kind::<R>())
 }
 
 #[cfg(test)]
 mod tests {
     use test_utils;
     use runtime::Object;
-    use super::Message;
+    use super::{CachedMethod, Message, MessageError, NilPolicy};
+    use super::invalidate_verified_methods;
 
     #[test]
     fn test_send_message() {
@@ -276,7 +426,6 @@ mod tests {
         assert!(result == expected);
     }
 
-    #[cfg(not(feature = "verify_message"))]
     #[test]
     fn test_send_message_nil() {
         let nil: *mut Object = ::std::ptr::null_mut();
@@ -294,6 +443,94 @@ mod tests {
             msg_send![nil, doubleValue]
         };
         assert!(result == 0.0);
+
+        let result: f32 = unsafe {
+            msg_send![nil, floatValue]
+        };
+        assert!(result == 0.0);
+
+        let result: test_utils::CustomStruct = unsafe {
+            msg_send![nil, customStruct]
+        };
+        let expected = test_utils::CustomStruct { a: 0, b: 0, c: 0, d: 0 };
+        assert!(result == expected);
+    }
+
+    #[test]
+    fn test_send_message_super_nil() {
+        let nil: *mut Object = ::std::ptr::null_mut();
+        let superclass = test_utils::custom_class();
+        let result: test_utils::CustomStruct = unsafe {
+            msg_send![super(nil, superclass), customStruct]
+        };
+        let expected = test_utils::CustomStruct { a: 0, b: 0, c: 0, d: 0 };
+        assert!(result == expected);
+
+        let result: f64 = unsafe {
+            msg_send![super(nil, superclass), doubleValue]
+        };
+        assert!(result == 0.0);
+    }
+
+    #[test]
+    fn test_send_message_nil_policy() {
+        let nil: *mut Object = ::std::ptr::null_mut();
+        let result: Result<usize, _> = unsafe {
+            ::message::send_message_with_nil_policy(nil, sel!(hash), (),
+                NilPolicy::Error)
+        };
+        match result {
+            Err(MessageError::NilReceiver(sel)) => assert!(sel == sel!(hash)),
+            _ => panic!("Expected a nil receiver error"),
+        }
+
+        let result: Result<usize, _> = unsafe {
+            ::message::send_message_verified_with_nil_policy(nil, sel!(hash),
+                (), NilPolicy::Error)
+        };
+        assert!(result.is_err());
+
+        let superclass = test_utils::custom_class();
+        let result: Result<u32, _> = unsafe {
+            ::message::send_super_message_with_nil_policy(nil, superclass,
+                sel!(foo), (), NilPolicy::Error)
+        };
+        assert!(result.is_err());
+
+        let result: usize = unsafe {
+            msg_send![nil = NilPolicy::ReturnZero; nil, hash]
+        };
+        assert!(result == 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_send_message_nil_error_panics() {
+        let nil: *mut Object = ::std::ptr::null_mut();
+        let _: usize = unsafe {
+            msg_send![nil = NilPolicy::Error; nil, hash]
+        };
+    }
+
+    #[test]
+    fn test_send_message_verified() {
+        let obj = test_utils::custom_object();
+        let result: Result<u32, _> = unsafe {
+            ::message::send_message_verified(&*obj, sel!(foo), ())
+        };
+        assert!(result.unwrap() == 0);
+
+        // Mismatched types are reported instead of being sent
+        let result: Result<u64, _> = unsafe {
+            ::message::send_message_verified(&*obj, sel!(foo), ())
+        };
+        assert!(result.is_err());
+
+        let result: u32 = unsafe {
+            let _: () = msg_send_verified![obj, setFoo:4u32];
+            msg_send_verified![obj, foo]
+        };
+        assert!(result == 4);
     }
 
     #[test]
@@ -311,6 +548,66 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_send_class_message_super() {
+        let subclass = test_utils::custom_subclass();
+        let superclass = test_utils::custom_class();
+        unsafe {
+            let foo: u32 = msg_send![super(subclass, superclass.metaclass()), classFoo];
+            assert!(foo == 7);
+            // Verification doesn't change the method that is invoked
+            let foo: u32 = msg_send_verified![super(subclass, superclass.metaclass()), classFoo];
+            assert!(foo == 7);
+        }
+    }
+
+    #[test]
+    fn test_verify_super_message() {
+        let obj = test_utils::custom_subclass_object();
+        let superclass = test_utils::custom_class();
+        assert!(obj.verify_super_message::<(), u32>(superclass, sel!(foo)).is_ok());
+        assert!(obj.verify_super_message::<(), u32>(superclass, sel!(classFoo)).is_err());
+
+        let subclass = test_utils::custom_subclass();
+        assert!(subclass.verify_message::<(), u32>(sel!(classFoo)).is_ok());
+        assert!(subclass.verify_super_message::<(), u32>(superclass, sel!(classFoo)).is_ok());
+        assert!(subclass.verify_super_message::<(), u32>(superclass.metaclass(), sel!(classFoo)).is_ok());
+    }
+
+    #[test]
+    fn test_verify_message_cached() {
+        let obj = test_utils::custom_object();
+        let cls = test_utils::custom_class();
+        // Repeated verifications are served from the cache
+        for _ in 0..2 {
+            assert!(obj.verify_message::<(), u32>(sel!(foo)).is_ok());
+            assert!(obj.verify_message::<(), u64>(sel!(foo)).is_err());
+        }
+
+        invalidate_verified_methods(cls);
+        assert!(obj.verify_message::<(), u32>(sel!(foo)).is_ok());
+        assert!(obj.verify_message::<(), u64>(sel!(foo)).is_err());
+    }
+
+    #[test]
+    fn test_cached_method() {
+        let obj = test_utils::custom_object();
+        let cls = test_utils::custom_class();
+        let set_foo = CachedMethod::<(u32,), ()>::new(cls, sel!(setFoo:)).unwrap();
+        let foo = CachedMethod::<(), u32>::new(cls, sel!(foo)).unwrap();
+        assert!(!foo.is_stale());
+        unsafe {
+            for i in 0..3 {
+                set_foo.invoke(&*obj, (i,)).unwrap();
+                assert!(foo.invoke(&*obj, ()).unwrap() == i);
+            }
+        }
+
+        // Mismatched signatures are rejected up front
+        assert!(CachedMethod::<(), u64>::new(cls, sel!(foo)).is_err());
+        assert!(CachedMethod::<(), u32>::new(cls, sel!(bar)).is_err());
+    }
+
     #[test]
     fn test_verify_message() {
         let obj = test_utils::custom_object();
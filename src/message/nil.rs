@@ -0,0 +1,67 @@
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use runtime::Sel;
+use super::MessageError;
+
+/**
+The policy for messages sent to nil.
+
+The process-wide policy applies to `msg_send!`, `msg_send_verified!` and
+`Message::send_message`; it can be overridden for a single call site with
+`msg_send![nil = policy; ...]`. The default policy is `ReturnZero`, matching
+the behaviour of Objective-C.
+
+Messages to nil are never verified, since there is no method to verify
+against.
+
+# Example
+```
+use objc::NilPolicy;
+
+// Catch messages to nil while debugging
+NilPolicy::set(NilPolicy::DebugPanic);
+```
+*/
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NilPolicy {
+    /// The message returns a zeroed value without being sent.
+    ReturnZero,
+    /// The message returns a `MessageError::NilReceiver` without being sent.
+    Error,
+    /// The message panics when `debug_assertions` are enabled, and otherwise
+    /// returns a zeroed value.
+    DebugPanic,
+}
+
+static NIL_POLICY: AtomicUsize = AtomicUsize::new(NilPolicy::ReturnZero as usize);
+
+impl NilPolicy {
+    /// Returns the current process-wide nil policy.
+    pub fn current() -> NilPolicy {
+        match NIL_POLICY.load(Ordering::Relaxed) {
+            1 => NilPolicy::Error,
+            2 => NilPolicy::DebugPanic,
+            _ => NilPolicy::ReturnZero,
+        }
+    }
+
+    /// Sets the process-wide nil policy.
+    pub fn set(policy: NilPolicy) {
+        NIL_POLICY.store(policy as usize, Ordering::Relaxed);
+    }
+}
+
+/// Returns the result of sending `sel` to nil under the given policy.
+///
+/// The runtimes only guarantee zeroed returns for some types (GNUstep's nil
+/// IMP doesn't zero large structs), so nil receivers are handled here rather
+/// than being dispatched.
+pub unsafe fn nil_return<R>(sel: Sel, policy: NilPolicy) -> Result<R, MessageError> {
+    match policy {
+        NilPolicy::Error => Err(MessageError::NilReceiver(sel)),
+        NilPolicy::DebugPanic if cfg!(debug_assertions) =>
+            panic!("Messaging {:?} to nil", sel),
+        _ => Ok(mem::zeroed()),
+    }
+}
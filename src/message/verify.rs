@@ -1,35 +1,181 @@
+use std::any::TypeId;
+use std::collections::HashSet;
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{OnceLock, RwLock};
+
 use runtime::{Class, Object, Sel};
-use {Encode, EncodeArguments};
+use {Encode, EncodeArguments, Encoding};
 use super::MessageError;
 
+/**
+The process-wide mode controlling how messages are verified when sent with
+`msg_send_verified!`, or with `msg_send!` when the `"verify_message"` feature
+is enabled.
+
+Explicit calls to `Message::verify_message` are not affected by the mode.
+The default mode is `Always`.
+
+# Example
+```
+use objc::VerificationMode;
+
+// Keep verification in builds with debug assertions only
+VerificationMode::set(VerificationMode::Debug);
+```
+*/
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VerificationMode {
+    /// Messages are never verified.
+    Off,
+    /// Messages are verified only when `debug_assertions` are enabled,
+    /// and are otherwise sent without verification.
+    Debug,
+    /// Messages are always verified; a mismatch is returned as an error
+    /// and the message is not sent.
+    Always,
+    /// Messages are always verified; a mismatch is logged to stderr and the
+    /// message is sent anyway.
+    Warn,
+}
+
+static VERIFICATION_MODE: AtomicUsize = AtomicUsize::new(VerificationMode::Always as usize);
+
+impl VerificationMode {
+    /// Returns the current process-wide verification mode.
+    pub fn current() -> VerificationMode {
+        match VERIFICATION_MODE.load(Ordering::Relaxed) {
+            0 => VerificationMode::Off,
+            1 => VerificationMode::Debug,
+            3 => VerificationMode::Warn,
+            _ => VerificationMode::Always,
+        }
+    }
+
+    /// Sets the process-wide verification mode.
+    pub fn set(mode: VerificationMode) {
+        VERIFICATION_MODE.store(mode as usize, Ordering::Relaxed);
+    }
+}
+
+/// Verifies a message to be sent to an instance of `cls` as dictated by the
+/// current `VerificationMode`.
+/// Returns an error only if the message should not be sent.
+pub fn verify_for_mode<A, R>(cls: &Class, sel: Sel)
+        -> Result<(), MessageError>
+        where A: EncodeArguments, R: Encode {
+    let mode = VerificationMode::current();
+    match mode {
+        VerificationMode::Off => return Ok(()),
+        VerificationMode::Debug if !cfg!(debug_assertions) => return Ok(()),
+        _ => (),
+    }
+
+    match verify_message_signature::<A, R>(cls, sel) {
+        Err(err) if mode == VerificationMode::Warn => {
+            eprintln!("objc: unverified message sent: {}", err);
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// A successfully verified (class, selector, signature) combination.
+/// The signature is identified by the type of its arguments and return value.
+type VerifiedKey = (usize, Sel, TypeId);
+
+fn verified_cache() -> &'static RwLock<HashSet<VerifiedKey>> {
+    static CACHE: OnceLock<RwLock<HashSet<VerifiedKey>>> = OnceLock::new();
+    CACHE.get_or_init(|| RwLock::new(HashSet::new()))
+}
+
+trait NonStaticAny {
+    fn get_type_id(&self) -> TypeId where Self: 'static;
+}
+
+impl<T: ?Sized> NonStaticAny for PhantomData<T> {
+    fn get_type_id(&self) -> TypeId where Self: 'static {
+        TypeId::of::<T>()
+    }
+}
+
+/// Returns the `TypeId` of `T` with its lifetimes erased, so that arguments
+/// containing references can be identified; encodings never depend on
+/// lifetimes.
+fn signature_type_id<T: ?Sized>() -> TypeId {
+    let phantom = PhantomData::<T>;
+    let phantom: &dyn NonStaticAny = &phantom;
+    // Lifetimes don't affect the TypeId, this only satisfies the bound
+    let phantom = unsafe {
+        mem::transmute::<&dyn NonStaticAny, &(dyn NonStaticAny + 'static)>(phantom)
+    };
+    phantom.get_type_id()
+}
+
+fn inherits_from(cls: &Class, ancestor: &Class) -> bool {
+    let mut cls = Some(cls);
+    while let Some(c) = cls {
+        if c == ancestor {
+            return true;
+        }
+        cls = c.superclass();
+    }
+    false
+}
+
+/// Forgets the cached verifications for the given class and its subclasses.
+/// This must be called whenever the methods of a class change or before it is
+/// disposed, since its subclasses may inherit the changed methods.
+pub fn invalidate_verified_methods(cls: &Class) {
+    let mut cache = verified_cache().write().unwrap_or_else(|e| e.into_inner());
+    cache.retain(|&(entry_cls, _, _)| {
+        !inherits_from(unsafe { &*(entry_cls as *const Class) }, cls)
+    });
+}
+
 pub fn verify_message_signature<A, R>(cls: &Class, sel: Sel)
         -> Result<(), MessageError>
         where A: EncodeArguments, R: Encode {
+    let key = (cls as *const Class as usize, sel, signature_type_id::<(A, R)>());
+    let cached = verified_cache().read()
+        .map(|cache| cache.contains(&key))
+        .unwrap_or(false);
+    if cached {
+        return Ok(());
+    }
+
+    let ret = R::encode();
+    let args = A::encodings();
+    verify_uncached(cls, sel, &ret, args.as_ref())?;
+    verified_cache().write().unwrap_or_else(|e| e.into_inner()).insert(key);
+    Ok(())
+}
+
+fn verify_uncached(cls: &Class, sel: Sel, ret: &Encoding, args: &[Encoding])
+        -> Result<(), MessageError> {
     let method = match cls.instance_method(sel) {
         Some(method) => method,
-        None => return Err(MessageError(
+        None => return Err(MessageError::Verification(
             format!("Method {:?} not found on class {:?}",
                 sel, cls)
         )),
     };
 
-    let ret = R::encode();
     let expected_ret = method.return_type();
-    if ret != expected_ret {
-        return Err(MessageError(
+    if *ret != expected_ret {
+        return Err(MessageError::Verification(
             format!("Return type code {:?} does not match expected {:?} for method {:?}",
                 ret, expected_ret, method.name())
         ));
     }
 
     let self_and_cmd = [<*mut Object>::encode(), Sel::encode()];
-    let args = A::encodings();
-    let args = args.as_ref();
 
     let count = self_and_cmd.len() + args.len();
     let expected_count = method.arguments_count();
     if count != expected_count {
-        return Err(MessageError(
+        return Err(MessageError::Verification(
             format!("Method {:?} accepts {} arguments, but {} were given",
                 method.name(), expected_count, count)
         ));
@@ -38,7 +184,7 @@ pub fn verify_message_signature<A, R>(cls: &Class, sel: Sel)
     for (i, arg) in self_and_cmd.iter().chain(args).enumerate() {
         let expected = method.argument_type(i).unwrap();
         if *arg != expected {
-            return Err(MessageError(
+            return Err(MessageError::Verification(
                 format!("Method {:?} expected argument at index {} with type code {:?} but was given {:?}",
                     method.name(), i, expected, arg)
             ));
@@ -1,46 +1,35 @@
-use std::any::{Any, TypeId};
-use std::mem;
-
 use runtime::{Object, Imp, Sel};
 use super::Super;
+use super::abi::MsgSendKind;
 
-pub fn msg_send_fn<R: Any>(obj: *mut Object, _: Sel) -> (Imp, *mut Object) {
-    // Structures 1 or 2 bytes in size are placed in EAX.
-    // Structures 4 or 8 bytes in size are placed in: EAX and EDX.
-    // Structures of other sizes are placed at the address supplied by the caller.
-    // <https://developer.apple.com/library/mac/documentation/DeveloperTools/Conceptual/LowLevelABI/130-IA-32_Function_Calling_Conventions/IA32.html>
-
+pub fn msg_send_fn(obj: *mut Object, _: Sel, kind: MsgSendKind) -> (Imp, *mut Object) {
     extern {
         fn objc_msgSend();
         fn objc_msgSend_fpret();
         fn objc_msgSend_stret();
     }
 
-    let type_id = TypeId::of::<R>();
-    let size = mem::size_of::<R>();
-    let msg_fn = if type_id == TypeId::of::<f32>() ||
-            type_id == TypeId::of::<f64>() {
-        objc_msgSend_fpret
-    } else if size == 0 || size == 1 || size == 2 || size == 4 || size == 8 {
-        objc_msgSend
-    } else {
-        objc_msgSend_stret
+    let msg_fn = match kind {
+        MsgSendKind::Normal => objc_msgSend,
+        // There is no fp2ret variant on x86, and the classifier never needs it
+        MsgSendKind::Fpret | MsgSendKind::Fp2ret => objc_msgSend_fpret,
+        MsgSendKind::Stret => objc_msgSend_stret,
     };
 
     (msg_fn, obj)
 }
 
-pub fn msg_send_super_fn<R: Any>(sup: &Super, _: Sel) -> (Imp, *mut Object) {
+pub fn msg_send_super_fn(sup: &Super, _: Sel, kind: MsgSendKind) -> (Imp, *mut Object) {
     extern {
         fn objc_msgSendSuper();
         fn objc_msgSendSuper_stret();
     }
 
-    let size = mem::size_of::<R>();
-    let msg_fn = if size == 0 || size == 1 || size == 2 || size == 4 || size == 8 {
-        objc_msgSendSuper
-    } else {
-        objc_msgSendSuper_stret
+    // The receiver of a message to super is never nil,
+    // so floating point returns don't need special handling
+    let msg_fn = match kind {
+        MsgSendKind::Stret => objc_msgSendSuper_stret,
+        _ => objc_msgSendSuper,
     };
 
     (msg_fn, sup as *const Super as *mut Object)
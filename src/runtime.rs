@@ -3,8 +3,10 @@
 //! For more information on foreign functions, see Apple's documentation:
 //! <https://developer.apple.com/library/mac/documentation/Cocoa/Reference/ObjCRuntimeRef/index.html>
 
+use std::error::Error;
 use std::ffi::{CStr, CString};
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::os::raw::{c_char, c_int, c_uint, c_void};
 use std::ptr;
 use std::str;
@@ -54,6 +56,30 @@ pub struct Method {
     _priv: PrivateMarker,
 }
 
+/// A description of a method declared by a protocol.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MethodDescription {
+    name: Sel,
+    types: *const c_char,
+}
+
+/// A type that represents a property declared by a class or protocol.
+#[repr(C)]
+pub struct Property {
+    _priv: PrivateMarker,
+}
+
+/// An attribute of a property, as passed to `class_addProperty` and
+/// `protocol_addProperty`.
+#[repr(C)]
+pub(crate) struct PropertyAttribute {
+    /// The name of the attribute, such as `T` for the type.
+    pub name: *const c_char,
+    /// The value of the attribute, which is empty for most attributes.
+    pub value: *const c_char,
+}
+
 /// A type that represents an Objective-C class.
 #[repr(C)]
 pub struct Class {
@@ -82,14 +108,21 @@ extern {
 
     pub fn class_getName(cls: *const Class) -> *const c_char;
     pub fn class_getSuperclass(cls: *const Class) -> *const Class;
+    pub(crate) fn class_isMetaClass(cls: *const Class) -> BOOL;
     pub fn class_getInstanceSize(cls: *const Class) -> usize;
     pub fn class_getInstanceMethod(cls: *const Class, sel: Sel) -> *const Method;
+    pub(crate) fn class_getMethodImplementation(cls: *const Class, name: Sel) -> Imp;
     pub fn class_getInstanceVariable(cls: *const Class, name: *const c_char) -> *const Ivar;
     pub fn class_copyMethodList(cls: *const Class, outCount: *mut c_uint) -> *mut *const Method;
     pub fn class_copyIvarList(cls: *const Class, outCount: *mut c_uint) -> *mut *const Ivar;
     pub fn class_addMethod(cls: *mut Class, name: Sel, imp: Imp, types: *const c_char) -> BOOL;
+    pub(crate) fn class_replaceMethod(cls: *mut Class, name: Sel, imp: Imp, types: *const c_char) -> Option<Imp>;
     pub fn class_addIvar(cls: *mut Class, name: *const c_char, size: usize, alignment: u8, types: *const c_char) -> BOOL;
     pub fn class_addProtocol(cls: *mut Class, proto: *const Protocol) -> BOOL;
+    pub(crate) fn class_addProperty(cls: *mut Class, name: *const c_char, attributes: *const PropertyAttribute,
+                             attributeCount: c_uint) -> BOOL;
+    pub(crate) fn class_getProperty(cls: *const Class, name: *const c_char) -> *const Property;
+    pub(crate) fn class_copyPropertyList(cls: *const Class, outCount: *mut c_uint) -> *mut *const Property;
     pub fn class_conformsToProtocol(cls: *const Class, proto: *const Protocol) -> BOOL;
     pub fn class_copyProtocolList(cls: *const Class, outCount: *mut c_uint) -> *mut *const Protocol;
 
@@ -112,6 +145,12 @@ extern {
     pub fn protocol_addMethodDescription(proto: *mut Protocol, name: Sel, types: *const c_char, isRequiredMethod: BOOL,
                                          isInstanceMethod: BOOL);
     pub fn protocol_addProtocol(proto: *mut Protocol, addition: *const Protocol);
+    pub(crate) fn protocol_addProperty(proto: *mut Protocol, name: *const c_char, attributes: *const PropertyAttribute,
+                                attributeCount: c_uint, isRequiredProperty: BOOL, isInstanceProperty: BOOL);
+    pub(crate) fn protocol_getProperty(proto: *const Protocol, name: *const c_char, isRequiredProperty: BOOL,
+                                isInstanceProperty: BOOL) -> *const Property;
+    pub(crate) fn protocol_copyMethodDescriptionList(proto: *const Protocol, isRequiredMethod: BOOL, isInstanceMethod: BOOL,
+                                              outCount: *mut c_uint) -> *mut MethodDescription;
     pub fn protocol_getName(proto: *const Protocol) -> *const c_char;
     pub fn protocol_isEqual(proto: *const Protocol, other: *const Protocol) -> BOOL;
     pub fn protocol_copyProtocolList(proto: *const Protocol, outCount: *mut c_uint) -> *mut *const Protocol;
@@ -121,6 +160,9 @@ extern {
     pub fn ivar_getOffset(ivar: *const Ivar) -> isize;
     pub fn ivar_getTypeEncoding(ivar: *const Ivar) -> *const c_char;
 
+    pub(crate) fn property_getName(property: *const Property) -> *const c_char;
+    pub(crate) fn property_getAttributes(property: *const Property) -> *const c_char;
+
     pub fn method_getName(method: *const Method) -> Sel;
     pub fn method_getImplementation(method: *const Method) -> Imp;
     pub fn method_copyReturnType(method: *const Method) -> *mut c_char;
@@ -128,6 +170,21 @@ extern {
     pub fn method_getNumberOfArguments(method: *const Method) -> c_uint;
     pub fn method_setImplementation(method: *mut Method, imp: Imp) -> Imp;
     pub fn method_exchangeImplementations(m1: *mut Method, m2: *mut Method);
+
+    pub(crate) fn imp_implementationWithBlock(block: *mut Object) -> Imp;
+    pub(crate) fn imp_removeBlock(imp: Imp) -> BOOL;
+
+    pub(crate) fn objc_loadWeakRetained(location: *mut *mut Object) -> *mut Object;
+    pub(crate) fn objc_storeWeak(location: *mut *mut Object, obj: *mut Object) -> *mut Object;
+}
+
+#[link(name = "objc", kind = "dylib")]
+extern "C-unwind" {
+    pub(crate) fn objc_exception_throw(exception: *mut Object) -> !;
+}
+
+extern "C" {
+    pub(crate) fn free(ptr: *mut c_void);
 }
 
 impl Sel {
@@ -157,6 +214,12 @@ impl PartialEq for Sel {
 
 impl Eq for Sel { }
 
+impl Hash for Sel {
+    fn hash<H>(&self, state: &mut H) where H: Hasher {
+        self.ptr.hash(state);
+    }
+}
+
 // Sel is safe to share across threads because it is immutable
 unsafe impl Sync for Sel { }
 unsafe impl Send for Sel { }
@@ -200,6 +263,51 @@ impl Ivar {
     }
 }
 
+impl MethodDescription {
+    /// Returns the selector of the described method.
+    pub fn name(&self) -> Sel {
+        self.name
+    }
+
+    /// Returns the type encoding of the described method's return and
+    /// argument types, which may include offsets and extended type
+    /// information.
+    pub fn types(&self) -> &str {
+        if self.types.is_null() {
+            return "";
+        }
+        let types = unsafe {
+            CStr::from_ptr(self.types)
+        };
+        str::from_utf8(types.to_bytes()).unwrap()
+    }
+}
+
+impl fmt::Debug for MethodDescription {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} {}", self.name, self.types())
+    }
+}
+
+impl Property {
+    /// Returns the name of self.
+    pub fn name(&self) -> &str {
+        let name = unsafe {
+            CStr::from_ptr(property_getName(self))
+        };
+        str::from_utf8(name.to_bytes()).unwrap()
+    }
+
+    /// Returns the attribute string of self, which describes its type,
+    /// memory semantics and accessors, like `T@,&,N,V_name`.
+    pub fn attributes(&self) -> &str {
+        let attributes = unsafe {
+            CStr::from_ptr(property_getAttributes(self))
+        };
+        str::from_utf8(attributes.to_bytes()).unwrap()
+    }
+}
+
 impl Method {
     /// Returns the name of self.
     pub fn name(&self) -> Sel {
@@ -295,6 +403,11 @@ impl Class {
         }
     }
 
+    /// Returns true if self is a metaclass.
+    pub fn is_metaclass(&self) -> bool {
+        unsafe { class_isMetaClass(self) != NO }
+    }
+
     /// Returns the size of instances of self.
     pub fn instance_size(&self) -> usize {
         unsafe {
@@ -322,6 +435,25 @@ impl Class {
         }
     }
 
+    /// Returns the property of self with the given name, or `None` if self
+    /// and its superclasses have no property with the given name.
+    pub fn property(&self, name: &str) -> Option<&Property> {
+        let name = CString::new(name).unwrap();
+        unsafe {
+            let property = class_getProperty(self, name.as_ptr());
+            if property.is_null() { None } else { Some(&*property) }
+        }
+    }
+
+    /// Describes the properties declared by self.
+    pub fn properties(&self) -> MallocBuffer<&Property> {
+        unsafe {
+            let mut count: c_uint = 0;
+            let properties = class_copyPropertyList(self, &mut count);
+            MallocBuffer::new(properties as *mut _, count as usize).unwrap()
+        }
+    }
+
     /// Describes the instance methods implemented by self.
     pub fn instance_methods(&self) -> MallocBuffer<&Method> {
         unsafe {
@@ -392,6 +524,30 @@ impl Protocol {
         }
     }
 
+    /// Returns the instance property of self with the given name, or `None`
+    /// if self declares no such property that is required or optional, as
+    /// specified.
+    pub fn property(&self, name: &str, is_required: bool) -> Option<&Property> {
+        let name = CString::new(name).unwrap();
+        unsafe {
+            let property = protocol_getProperty(self, name.as_ptr(),
+                is_required as BOOL, YES);
+            if property.is_null() { None } else { Some(&*property) }
+        }
+    }
+
+    /// Describes the methods declared by self that are required or optional
+    /// and instance or class methods, as specified.
+    pub fn method_descriptions(&self, is_required: bool, is_instance: bool)
+            -> MallocBuffer<MethodDescription> {
+        unsafe {
+            let mut count: c_uint = 0;
+            let descriptions = protocol_copyMethodDescriptionList(self,
+                is_required as BOOL, is_instance as BOOL, &mut count);
+            MallocBuffer::new(descriptions, count as usize).unwrap()
+        }
+    }
+
     /// Get a list of the protocols to which this protocol conforms.
     pub fn adopted_protocols(&self) -> MallocBuffer<&Protocol> {
         unsafe {
@@ -437,26 +593,63 @@ impl Object {
         }
     }
 
+    /// Returns the offset of the ivar of self with the given name, checking
+    /// that it has the encoding of `T`.
+    fn ivar_offset<T>(&self, name: &str) -> Result<isize, IvarError>
+            where T: Encode {
+        let ivar = self.class().instance_variable(name)
+            .ok_or_else(|| IvarError::NotFound(name.to_owned()))?;
+        let encoding = ivar.type_encoding();
+        if encoding == T::encode() {
+            Ok(ivar.offset())
+        } else {
+            Err(IvarError::TypeMismatch {
+                name: name.to_owned(),
+                expected: T::encode(),
+                actual: encoding,
+            })
+        }
+    }
+
+    /// Returns a reference to the ivar of self with the given name.
+    /// Returns an error if self has no ivar with the given name or its
+    /// encoding differs from that of `T`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the ivar is actually of type `T`; only its
+    /// encoding is checked.
+    pub unsafe fn try_get_ivar<T>(&self, name: &str) -> Result<&T, IvarError>
+            where T: Encode {
+        let offset = self.ivar_offset::<T>(name)?;
+        let self_ptr: *const Object = self;
+        Ok(&*((self_ptr as *const u8).offset(offset) as *const T))
+    }
+
+    /// Returns a mutable reference to the ivar of self with the given name.
+    /// Returns an error if self has no ivar with the given name or its
+    /// encoding differs from that of `T`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the ivar is actually of type `T`; only its
+    /// encoding is checked.
+    pub unsafe fn try_get_mut_ivar<T>(&mut self, name: &str)
+            -> Result<&mut T, IvarError> where T: Encode {
+        let offset = self.ivar_offset::<T>(name)?;
+        let self_ptr: *mut Object = self;
+        Ok(&mut *((self_ptr as *mut u8).offset(offset) as *mut T))
+    }
+
     /// Returns a reference to the ivar of self with the given name.
     /// Panics if self has no ivar with the given name.
     /// Unsafe because the caller must ensure that the ivar is actually
     /// of type `T`.
     pub unsafe fn get_ivar<T>(&self, name: &str) -> &T where T: Encode {
-        let offset = {
-            let cls = self.class();
-            match cls.instance_variable(name) {
-                Some(ivar) => {
-                    assert!(ivar.type_encoding() == T::encode());
-                    ivar.offset()
-                }
-                None => panic!("Ivar {} not found on class {:?}", name, cls),
-            }
-        };
-        let ptr = {
-            let self_ptr: *const Object = self;
-            (self_ptr as *const u8).offset(offset) as *const T
-        };
-        &*ptr
+        match self.try_get_ivar(name) {
+            Ok(ivar) => ivar,
+            Err(e) => panic!("{} on class {:?}", e, self.class()),
+        }
     }
 
     /// Returns a mutable reference to the ivar of self with the given name.
@@ -465,21 +658,11 @@ impl Object {
     /// of type `T`.
     pub unsafe fn get_mut_ivar<T>(&mut self, name: &str) -> &mut T
             where T: Encode {
-        let offset = {
-            let cls = self.class();
-            match cls.instance_variable(name) {
-                Some(ivar) => {
-                    assert!(ivar.type_encoding() == T::encode());
-                    ivar.offset()
-                }
-                None => panic!("Ivar {} not found on class {:?}", name, cls),
-            }
-        };
-        let ptr = {
-            let self_ptr: *mut Object = self;
-            (self_ptr as *mut u8).offset(offset) as *mut T
-        };
-        &mut *ptr
+        let cls: *const Class = self.class();
+        match self.try_get_mut_ivar(name) {
+            Ok(ivar) => ivar,
+            Err(e) => panic!("{} on class {:?}", e, *cls),
+        }
     }
 
     /// Sets the value of the ivar of self with the given name.
@@ -492,6 +675,44 @@ impl Object {
     }
 }
 
+/// An error accessing an ivar of an object by name.
+#[derive(Clone, Debug, PartialEq)]
+pub enum IvarError {
+    /// The object's class has no ivar with the given name.
+    NotFound(String),
+    /// The ivar with the given name has a different encoding than the type
+    /// it was accessed as.
+    TypeMismatch {
+        /// The name of the ivar.
+        name: String,
+        /// The encoding of the type the ivar was accessed as.
+        expected: Encoding,
+        /// The encoding of the ivar.
+        actual: Encoding,
+    },
+}
+
+impl fmt::Display for IvarError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IvarError::NotFound(ref name) =>
+                write!(f, "Ivar {} not found", name),
+            IvarError::TypeMismatch { ref name, ref expected, ref actual } =>
+                write!(f, "Ivar {} has encoding {:?}, but was accessed as {:?}",
+                    name, actual, expected),
+        }
+    }
+}
+
+impl Error for IvarError {
+    fn description(&self) -> &str {
+        match *self {
+            IvarError::NotFound(_) => "ivar not found",
+            IvarError::TypeMismatch { .. } => "ivar type mismatch",
+        }
+    }
+}
+
 impl fmt::Debug for Object {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "<{:?}: {:p}>", self.class(), self)
@@ -502,7 +723,7 @@ impl fmt::Debug for Object {
 mod tests {
     use test_utils;
     use Encode;
-    use super::{Class, Protocol, Sel};
+    use super::{Class, IvarError, Protocol, Sel};
 
     #[test]
     fn test_ivar() {
@@ -538,6 +759,8 @@ mod tests {
         assert!(cls.superclass().is_none());
 
         let metaclass = cls.metaclass();
+        assert!(metaclass.is_metaclass());
+        assert!(!cls.is_metaclass());
         // The metaclass of a root class is a subclass of the root class
         assert!(metaclass.superclass().unwrap() == cls);
 
@@ -599,4 +822,18 @@ mod tests {
         };
         assert!(result == 4);
     }
+
+    #[test]
+    fn test_try_get_ivar() {
+        let obj = test_utils::custom_object();
+        unsafe {
+            assert!(obj.try_get_ivar::<u32>("_foo").is_ok());
+            let err = obj.try_get_ivar::<u32>("_bar").err();
+            assert!(err == Some(IvarError::NotFound("_bar".to_owned())));
+            match obj.try_get_ivar::<u8>("_foo") {
+                Err(IvarError::TypeMismatch { .. }) => (),
+                _ => panic!("Expected a type mismatch"),
+            }
+        }
+    }
 }
@@ -1,7 +1,9 @@
 use std::ops::{Deref, DerefMut};
-use std::os::raw::c_char;
+use std::os::raw::{c_char, c_void};
+use std::ptr;
 use std::sync::{Once, ONCE_INIT};
 
+use block::Block;
 use declare::{ClassDecl, ProtocolDecl};
 use runtime::{Class, Object, Protocol, Sel, self};
 use {Encode, Encoding};
@@ -74,6 +76,47 @@ pub fn custom_class() -> &'static Class {
 
         decl.add_protocol(proto);
         decl.add_ivar::<u32>("_foo");
+        decl.add_ivar::<u32>("_retainCount");
+
+        // Minimal memory management, since there's no NSObject to inherit from
+        extern "C" fn custom_obj_alloc_with_zone(this: &Class, _cmd: Sel, _zone: *mut c_void) -> *mut Object {
+            unsafe { runtime::class_createInstance(this, 0) }
+        }
+
+        extern "C" fn custom_obj_alloc(this: &Class, _cmd: Sel) -> *mut Object {
+            unsafe { msg_send![this, allocWithZone:ptr::null_mut::<c_void>()] }
+        }
+
+        extern "C" fn custom_obj_new(this: &Class, _cmd: Sel) -> *mut Object {
+            unsafe {
+                let obj: *mut Object = msg_send![this, alloc];
+                msg_send![obj, init]
+            }
+        }
+
+        extern "C" fn custom_obj_init(this: &mut Object, _cmd: Sel) -> *mut Object {
+            this
+        }
+
+        extern "C" fn custom_obj_retain(this: &mut Object, _cmd: Sel) -> *mut Object {
+            unsafe { *this.get_mut_ivar::<u32>("_retainCount") += 1; }
+            this
+        }
+
+        extern "C" fn custom_obj_release(this: &mut Object, _cmd: Sel) {
+            unsafe {
+                let count = this.get_mut_ivar::<u32>("_retainCount");
+                if *count > 0 {
+                    *count -= 1;
+                } else {
+                    let _: () = msg_send![this, dealloc];
+                }
+            }
+        }
+
+        extern "C" fn custom_obj_dealloc(this: &mut Object, _cmd: Sel) {
+            unsafe { runtime::object_dispose(this); }
+        }
 
         extern fn custom_obj_set_foo(this: &mut Object, _cmd: Sel, foo: u32) {
             unsafe { this.set_ivar::<u32>("_foo", foo); }
@@ -99,13 +142,46 @@ pub fn custom_class() -> &'static Class {
             fst + snd
         }
 
+        extern "C" fn custom_obj_sum(_this: &Object, _cmd: Sel, a: u32, b: u32,
+                c: u32, d: u32, e: u32, f: u32, g: u32, h: u32, i: u32, j: u32,
+                k: u32, l: u32, m: u32, n: u32, o: u32, p: u32) -> u32 {
+            a + b + c + d + e + f + g + h + i + j + k + l + m + n + o + p
+        }
+
+        extern "C" fn custom_obj_invoke_int_block(_this: &Object, _cmd: Sel,
+                block: *mut Block<(), i32>) -> i32 {
+            unsafe { (*block).call(()) }
+        }
+
         unsafe {
+            let alloc_with_zone: extern "C" fn(&Class, Sel, *mut c_void) -> *mut Object =
+                custom_obj_alloc_with_zone;
+            decl.add_class_method(sel!(allocWithZone:), alloc_with_zone);
+            let alloc: extern "C" fn(&Class, Sel) -> *mut Object = custom_obj_alloc;
+            decl.add_class_method(sel!(alloc), alloc);
+            let new: extern "C" fn(&Class, Sel) -> *mut Object = custom_obj_new;
+            decl.add_class_method(sel!(new), new);
+            let init: extern "C" fn(&mut Object, Sel) -> *mut Object = custom_obj_init;
+            decl.add_method(sel!(init), init);
+            let retain: extern "C" fn(&mut Object, Sel) -> *mut Object = custom_obj_retain;
+            decl.add_method(sel!(retain), retain);
+            let release: extern "C" fn(&mut Object, Sel) = custom_obj_release;
+            decl.add_method(sel!(release), release);
+            let dealloc: extern "C" fn(&mut Object, Sel) = custom_obj_dealloc;
+            decl.add_method(sel!(dealloc), dealloc);
+
             let set_foo: extern fn(&mut Object, Sel, u32) = custom_obj_set_foo;
             decl.add_method(sel!(setFoo:), set_foo);
             let get_foo: extern fn(&Object, Sel) -> u32 = custom_obj_get_foo;
             decl.add_method(sel!(foo), get_foo);
             let get_struct: extern fn(&Object, Sel) -> CustomStruct = custom_obj_get_struct;
             decl.add_method(sel!(customStruct), get_struct);
+            let sum: extern "C" fn(&Object, Sel, u32, u32, u32, u32, u32, u32, u32,
+                u32, u32, u32, u32, u32, u32, u32, u32, u32) -> u32 = custom_obj_sum;
+            decl.add_method(sel!(sumA:b:c:d:e:f:g:h:i:j:k:l:m:n:o:p:), sum);
+            let invoke_int_block: extern "C" fn(&Object, Sel, *mut Block<(), i32>) -> i32 =
+                custom_obj_invoke_int_block;
+            decl.add_method(sel!(invokeIntBlock:), invoke_int_block);
             let class_method: extern fn(&Class, Sel) -> u32 = custom_obj_class_method;
             decl.add_class_method(sel!(classFoo), class_method);
 
@@ -158,28 +234,20 @@ pub fn custom_object() -> CustomObject {
 }
 
 pub fn custom_subclass() -> &'static Class {
-    static REGISTER_CUSTOM_SUBCLASS: Once = ONCE_INIT;
-
-    REGISTER_CUSTOM_SUBCLASS.call_once(|| {
-        let superclass = custom_class();
-        let mut decl = ClassDecl::new("CustomSubclassObject", superclass).unwrap();
-
-        extern fn custom_subclass_get_foo(this: &Object, _cmd: Sel) -> u32 {
-            let foo: u32 = unsafe {
-                msg_send![super(this, custom_class()), foo]
-            };
-            foo + 2
-        }
+    extern fn custom_subclass_get_foo(this: &Object, _cmd: Sel) -> u32 {
+        let foo: u32 = unsafe {
+            msg_send![super(this, custom_class()), foo]
+        };
+        foo + 2
+    }
 
+    let superclass = custom_class();
+    ClassDecl::get_or_register("CustomSubclassObject", superclass, |decl| {
         unsafe {
             let get_foo: extern fn(&Object, Sel) -> u32 = custom_subclass_get_foo;
             decl.add_method(sel!(foo), get_foo);
         }
-
-        decl.register();
-    });
-
-    Class::get("CustomSubclassObject").unwrap()
+    }).unwrap()
 }
 
 pub fn custom_subclass_object() -> CustomObject {